@@ -0,0 +1,71 @@
+use expensify::{TransactionList, TransactionListElement};
+use failure::Error;
+use perdiem::to_merchant;
+use rates::split_by_rate_set;
+use timeperiod::Span;
+use Context;
+use TimePeriod;
+
+impl TransactionList {
+    /// Create the overnight flat rates for each night following a day of `period`.
+    pub fn from_accommodation(ctx: Context, period: TimePeriod) -> Result<Self, Error> {
+        let (transaction_list, rate_sets) = period.into_accommodation_transactions(&ctx)?;
+        Ok(TransactionList {
+            transaction_list_type: "expenses".to_owned(),
+            employee_email: ctx.user.email.clone(),
+            transaction_list,
+            notes: rate_sets
+                .into_iter()
+                .map(|s| format!("Used accommodation rates of rate set {}.", s))
+                .collect(),
+            records: Vec::new(),
+        })
+    }
+}
+
+fn to_element(span: &Span, ctx: &Context) -> Result<TransactionListElement, Error> {
+    let date = span.first_day();
+    let destination = ctx.destination_on(date);
+    let amount = ctx
+        .rates
+        .accommodation(&ctx.user.country, destination, date)? as i32;
+    let num_days = span.num_days();
+    Ok(TransactionListElement {
+        created: span.created(),
+        currency: format!("{}", ctx.user.country.currency()),
+        merchant: to_merchant(num_days, ctx, destination, "Overnight Flat Rate", amount),
+        amount: amount * num_days as i32,
+        category: ctx.user.categories.accommodation.name.clone(),
+        tag: format!("{}:{}", ctx.user.project.clone(), ctx.user.tags.travel.name),
+        billable: ctx.user.tags.travel.billable,
+        reimbursable: true,
+        comment: span.comment(ctx),
+        external_id: None,
+    })
+}
+
+impl TimePeriod {
+    fn into_accommodation_transactions(
+        self,
+        ctx: &Context,
+    ) -> Result<(Vec<TransactionListElement>, Vec<String>), Error> {
+        let spans = self
+            .into_spans(ctx)?
+            .into_iter()
+            .flat_map(|span| span.split_by(|day| ctx.destination_on(day)))
+            .collect();
+        let (spans, rate_sets) = split_by_rate_set(
+            spans,
+            |date| {
+                ctx.rates
+                    .rate_set(&ctx.user.country, ctx.destination_on(date), date)
+            },
+            |date| Some(ctx.place_on(date)),
+        );
+        let elements = spans
+            .iter()
+            .map(|span| to_element(span, ctx))
+            .collect::<Result<_, _>>()?;
+        Ok((elements, rate_sets))
+    }
+}
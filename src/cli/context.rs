@@ -1,3 +1,6 @@
+use expend::holidays::{Holidays, State};
+use expend::record::{self, Record};
+use expend::{rates, RateTable};
 use failure::{bail, format_err, Error, ResultExt};
 use options::ContextSubcommand;
 use std::{
@@ -41,6 +44,12 @@ pub fn handle(from: Option<PathBuf>, cmd: ContextSubcommand) -> Result<(), Error
             travel_tag_name,
             travel_unbillable,
             category_per_diems_name,
+            category_mileage_name,
+            category_accommodation_name,
+            category_home_office_name,
+            time_zone,
+            week_start,
+            holidays,
         } => {
             let config_dir = config_dir;
             create_dir_all(&config_dir).with_context(|_| {
@@ -51,20 +60,50 @@ pub fn handle(from: Option<PathBuf>, cmd: ContextSubcommand) -> Result<(), Error
             })?;
 
             let context_file = file_path(&config_dir, &name);
+            let holidays = holidays
+                .into_iter()
+                .map(|h| {
+                    if h.parse::<State>().is_ok() {
+                        return Ok(h);
+                    }
+                    let path = Path::new(&h);
+                    if !path.is_file() {
+                        bail!(
+                            "'{}' is neither a file with holidays nor the code of a German federal state, which are {}",
+                            h,
+                            State::CODES.join(", ")
+                        );
+                    }
+                    Holidays::from_file(path)?;
+                    Ok(path
+                        .canonicalize()?
+                        .to_str()
+                        .ok_or_else(|| format_err!("Path '{}' is not valid UTF-8", h))?
+                        .to_owned())
+                })
+                .collect::<Result<_, Error>>()?;
 
             let context = UserContext {
                 project,
                 email,
                 country: country.parse()?,
-                destination: match destination.map(|s| s.parse()) {
-                    Some(Err(err)) => return Err(err),
-                    Some(Ok(d)) => Some(d),
+                destination: match destination {
+                    Some(d) => Some(rates_from_directory(&config_dir)?.destination(&d.parse()?)?),
                     None => None,
                 },
                 categories: Categories {
                     per_diems: Category {
                         name: category_per_diems_name,
                     },
+                    mileage: Category {
+                        name: category_mileage_name,
+                    },
+                    accommodation: Category {
+                        name: category_accommodation_name,
+                    },
+                    home_office: Category {
+                        name: category_home_office_name,
+                    },
                 },
                 tags: Tags {
                     travel: Tag {
@@ -72,6 +111,15 @@ pub fn handle(from: Option<PathBuf>, cmd: ContextSubcommand) -> Result<(), Error
                         billable: !travel_unbillable,
                     },
                 },
+                time_zone: match time_zone {
+                    Some(time_zone) => Some(time_zone.parse()?),
+                    None => None,
+                },
+                week_start: match week_start {
+                    Some(day) => Some(day.parse()?),
+                    None => None,
+                },
+                holidays,
             };
             serde_json::to_writer_pretty(
                 File::create(&context_file).with_context(|_| {
@@ -114,6 +162,66 @@ fn path_to_context_name(file: &Path) -> Option<String> {
     file.file_stem().map(|s| s.to_string_lossy().into_owned())
 }
 
+pub fn load(
+    directory: &Path,
+    name: &str,
+    reference_date: Option<String>,
+    comment: Option<String>,
+    include_holidays: bool,
+) -> Result<expend::Context, Error> {
+    let mut user = from_file_path(&file_path(directory, name))?;
+    let rates = rates_from_directory(directory)?;
+    if let Some(destination) = user.destination.take() {
+        user.destination = Some(rates.destination(&destination).with_context(|_| {
+            format!(
+                "The destination of context '{}' is unknown - use 'context set' to change it.",
+                name
+            )
+        })?);
+    }
+    let holidays = if include_holidays {
+        Holidays::default()
+    } else {
+        holidays_of(&user)?
+    };
+    let mut context = expend::Context {
+        name: name.to_owned(),
+        user,
+        reference_date: None,
+        comment,
+        rates,
+        holidays,
+        destinations: Default::default(),
+        meals: Default::default(),
+        record: Record::from_file(&directory.join(record::FILE_NAME))?,
+        posted: Default::default(),
+    };
+    if let Some(date) = reference_date {
+        context.reference_date = Some(expend::parse_reference_date(&date, context.today())?);
+    }
+    Ok(context)
+}
+
+fn holidays_of(user: &expend::UserContext) -> Result<Holidays, Error> {
+    user.holidays
+        .iter()
+        .try_fold(Holidays::default(), |holidays, h| {
+            Ok(holidays.merged(match h.parse() {
+                Ok(state) => Holidays::from_state(state),
+                Err(_) => Holidays::from_file(Path::new(h))?,
+            }))
+        })
+}
+
+fn rates_from_directory(directory: &Path) -> Result<RateTable, Error> {
+    let overrides = directory.join(rates::OVERRIDE_FILE_NAME);
+    Ok(if overrides.is_file() {
+        RateTable::default().merged(RateTable::from_file(&overrides)?)
+    } else {
+        RateTable::default()
+    })
+}
+
 pub fn from_file_path(file: &Path) -> Result<expend::UserContext, Error> {
     Ok(serde_json::from_reader(File::open(&file).with_context(|_| {
         format!(
@@ -0,0 +1,197 @@
+use chrono::{Date, Utc};
+use context;
+use expend::ledger::{self, Entry, Filter};
+use failure::{bail, format_err, Error, ResultExt};
+use options::History;
+use serde_json::Value;
+use std::{fs::create_dir_all, path::Path};
+
+/// Append `entry` to the ledger in `directory`. Unless it is pending, it takes the place of its pending entry.
+pub fn record(directory: &Path, entry: &Entry) -> Result<(), Error> {
+    create_dir_all(directory).with_context(|_| {
+        format!(
+            "Could not create configuration directory at '{}'",
+            directory.display()
+        )
+    })?;
+    let ledger = directory.join(ledger::FILE_NAME);
+    let failure = if entry.pending {
+        "Did not post as the post could not be added to the ledger"
+    } else if entry.failed {
+        "The post failed, and could not be marked as failed in the ledger"
+    } else {
+        "The post succeeded, but could not be added to the ledger"
+    };
+    ledger::append(&ledger, entry).context(failure)?;
+    Ok(())
+}
+
+/// The entry with the 1-based `number` in the ledger in `directory`, if it was not undone yet.
+pub fn entry_to_undo(directory: &Path, number: usize) -> Result<Entry, Error> {
+    let ledger = directory.join(ledger::FILE_NAME);
+    let mut entries = ledger::read(&ledger)?;
+    if number == 0 || number > entries.len() {
+        bail!(
+            "There is no post #{} in the ledger at '{}' - use the 'history' sub-command to see all posts.",
+            number,
+            ledger.display()
+        );
+    }
+    if entries[number - 1].pending {
+        bail!(
+            "Post #{} did not complete, and it is unknown whether Expensify created its expenses. Please check on Expensify and remove them there if needed.",
+            number
+        );
+    }
+    if entries[number - 1].failed {
+        bail!(
+            "Post #{} failed, so Expensify did not create its expenses.",
+            number
+        );
+    }
+    if let Some(undone_by) = entries
+        .iter()
+        .position(|e| e.succeeded() && e.undoes == Some(number))
+    {
+        bail!("Post #{} was undone already by #{}.", number, undone_by + 1);
+    }
+    Ok(entries.swap_remove(number - 1))
+}
+
+pub fn handle(directory: &Path, history: History) -> Result<(), Error> {
+    let today = today(directory, history.context.as_ref().map(String::as_str))?;
+    let filter = Filter {
+        from: parse_date(history.from, today)?,
+        to: parse_date(history.to, today)?,
+        context: history.context,
+        kind: history.kind,
+        min_amount: parse_amount(history.min_amount)?,
+        max_amount: parse_amount(history.max_amount)?,
+    };
+    let entries = ledger::read(&directory.join(ledger::FILE_NAME))?;
+    let mut count = 0;
+    for (number, entry) in entries.iter().enumerate() {
+        let transactions = match filter.transactions(entry) {
+            Some(transactions) => transactions,
+            None => continue,
+        };
+        count += 1;
+        println!(
+            "#{} {} on {} - {}{} ('{}'){}",
+            number + 1,
+            if entry.pending {
+                "attempted"
+            } else if entry.failed {
+                "failed"
+            } else {
+                "posted"
+            },
+            entry.timestamp.format("%Y-%m-%d %H:%M UTC"),
+            entry.kind,
+            entry
+                .context
+                .as_ref()
+                .map(|c| format!(" with context '{}'", c))
+                .unwrap_or_default(),
+            entry.payload_type,
+            if entry.pending {
+                " - outcome unknown"
+            } else {
+                ""
+            }
+        );
+        for transaction in transactions {
+            let field = |name| {
+                transaction
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+            };
+            let amount = transaction
+                .get("amount")
+                .and_then(Value::as_i64)
+                .unwrap_or_default();
+            println!(
+                "  {} {}{}.{:02} {} - {}{}",
+                field("created"),
+                if amount < 0 { "-" } else { "" },
+                amount.abs() / 100,
+                amount.abs() % 100,
+                field("currency"),
+                field("merchant"),
+                match field("comment") {
+                    "" => String::new(),
+                    comment => format!(" - {}", comment),
+                }
+            );
+        }
+    }
+    if count == 0 {
+        let ledger = directory.join(ledger::FILE_NAME);
+        if entries.is_empty() {
+            bail!(
+                "Nothing was posted yet according to the ledger at '{}'.",
+                ledger.display()
+            );
+        }
+        bail!(
+            "Did not find any matching posts in the ledger at '{}'.",
+            ledger.display()
+        );
+    }
+    Ok(())
+}
+
+/// Today's date in the time zone of the context shown, or of the default context if all are shown.
+/// Contexts which do not exist anymore fall back to UTC, as their posts are still in the ledger.
+fn today(directory: &Path, name: Option<&str>) -> Result<Date<Utc>, Error> {
+    let file = context::file_path(directory, name.unwrap_or("default"));
+    Ok(if file.is_file() {
+        context::from_file_path(&file)?
+            .time_zone
+            .unwrap_or_default()
+            .today()
+    } else {
+        Utc::today()
+    })
+}
+
+fn parse_date(date: Option<String>, today: Date<Utc>) -> Result<Option<Date<Utc>>, Error> {
+    Ok(match date {
+        Some(date) => Some(expend::parse_reference_date(&date, today)?),
+        None => None,
+    })
+}
+
+/// Parse amounts like '24', '-4.8' or '4.80' into cents.
+fn parse_amount(amount: Option<String>) -> Result<Option<i32>, Error> {
+    let amount = match amount {
+        Some(amount) => amount,
+        None => return Ok(None),
+    };
+    let invalid = || {
+        format_err!(
+            "Invalid amount '{}' - use something like '24' or '4.80'",
+            amount
+        )
+    };
+    let (negative, digits) = match amount.trim() {
+        a if a.starts_with('-') => (true, &a[1..]),
+        a => (false, a),
+    };
+    let (units, cents) = match digits.find('.') {
+        Some(pos) => (&digits[..pos], &digits[pos + 1..]),
+        None => (digits, ""),
+    };
+    let is_number = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if units.is_empty() || cents.len() > 2 || !is_number(units) || !is_number(cents) {
+        return Err(invalid());
+    }
+    let units: i32 = units.parse().map_err(|_| invalid())?;
+    let cents: i32 = format!("{:0<2}", cents).parse().map_err(|_| invalid())?;
+    let cents = units
+        .checked_mul(100)
+        .and_then(|u| u.checked_add(cents))
+        .ok_or_else(invalid)?;
+    Ok(Some(if negative { -cents } else { cents }))
+}
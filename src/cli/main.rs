@@ -1,3 +1,4 @@
+extern crate chrono;
 extern crate expend;
 extern crate failure;
 extern crate failure_tools;
@@ -14,12 +15,15 @@ extern crate username;
 
 mod context;
 mod credentials;
+mod history;
 mod options;
 
+use chrono::{Date, Utc};
+use expend::{expensify, trip::Trip};
 use failure::{bail, Error, ResultExt};
-use failure_tools::ok_or_exit;
+use failure_tools::print_causes;
 use options::*;
-use std::io::{stdin, stdout};
+use std::io::{stderr, stdin, stdout};
 
 pub enum Mode {
     DryRun,
@@ -32,7 +36,12 @@ fn exit_with(msg: &str) -> ! {
     std::process::exit(1)
 }
 
-fn confirm_payload(mode: Mode, type_name: &str, value: &serde_json::Value) -> Result<(), Error> {
+fn confirm_payload(
+    mode: Mode,
+    type_name: &str,
+    value: &serde_json::Value,
+    notes: &[String],
+) -> Result<(), Error> {
     use Mode::*;
     println!(
         "The following '{}' payload would be sent to Expensify:",
@@ -40,6 +49,12 @@ fn confirm_payload(mode: Mode, type_name: &str, value: &serde_json::Value) -> Re
     );
     serde_yaml::to_writer(stdout(), value)?;
     println!("\n");
+    for note in notes {
+        println!("Note: {}", note);
+    }
+    if !notes.is_empty() {
+        println!();
+    }
 
     match mode {
         DryRun => {
@@ -62,12 +77,167 @@ fn confirm_payload(mode: Mode, type_name: &str, value: &serde_json::Value) -> Re
     Ok(())
 }
 
+fn parse_meals(meals: Option<String>) -> Result<expend::perdiem::Meals, Error> {
+    Ok(match meals {
+        Some(meals) => meals.parse()?,
+        None => Default::default(),
+    })
+}
+
+fn with_meals(
+    mut context: expend::Context,
+    meals: Vec<String>,
+    trip: Option<&Trip>,
+) -> Result<expend::Context, Error> {
+    for meals in meals {
+        let days = context.override_meals(meals.parse()?)?;
+        check_covers_trip(&meals, &days, trip)?;
+    }
+    Ok(context)
+}
+
+fn with_destinations(
+    mut context: expend::Context,
+    destinations: Vec<String>,
+    trip: Option<&Trip>,
+) -> Result<expend::Context, Error> {
+    for destination in destinations {
+        let days = context.override_destination(destination.parse()?)?;
+        check_covers_trip(&destination, &days, trip)?;
+    }
+    Ok(context)
+}
+
+/// Fail if the override given as `input` applies to none of the `days` of `trip`, as it would be left out silently.
+fn check_covers_trip(input: &str, days: &[Date<Utc>], trip: Option<&Trip>) -> Result<(), Error> {
+    match trip {
+        Some(trip) if !days.iter().any(|day| trip.covers(day)) => bail!(
+            "The override '{}' does not apply to any day of the trip starting on {}",
+            input,
+            trip.first_day().format("%Y-%m-%d")
+        ),
+        _ => Ok(()),
+    }
+}
+
 fn show_value(value: serde_json::Value) -> Result<(), Error> {
     println!("Expensify said:",);
     serde_yaml::to_writer(stdout(), &value)?;
     Ok(())
 }
 
+fn client_and_mode(posting: &Posting) -> Result<(expensify::Client, Mode), Error> {
+    let (user, secret) = match (&posting.user_id, &posting.user_secret) {
+        (Some(ref user), Some(ref secret)) => (user.to_owned(), secret.to_owned()),
+        (Some(_), None) => exit_with("Please provide the secret as well with --user-secret."),
+        (None, Some(_)) => exit_with("Please provide the user as well with --user-id."),
+        (None, None) => match if posting.no_keychain {
+            None
+        } else {
+            let creds = credentials::from_keychain_or_clear(posting.clear_keychain_entry)?;
+            eprintln!("Using Expensify credentials from keychain.");
+            creds
+        } {
+            Some(creds) => creds,
+            None => credentials::query_from_user().and_then(|creds| {
+                if posting.no_keychain {
+                    Ok(creds)
+                } else {
+                    eprintln!("Storing credentials in keychain - use --no-keychain to disable.");
+                    credentials::store_in_keychain(creds)
+                }
+            })?,
+        },
+    };
+
+    let mode = match (posting.dry_run, posting.yes) {
+        (true, true) => exit_with("--auto-confirm and --dry-run are mutually exclusive."),
+        (true, false) => Mode::DryRun,
+        (false, true) => Mode::AutoConfirm,
+        (false, false) => Mode::Confirm,
+    };
+    Ok((
+        expensify::Client::new(posting.endpoint.clone(), user, secret),
+        mode,
+    ))
+}
+
+/// Post `cmd` and add it to the ledger. Before posting, it is added as pending entry, which is followed by
+/// the confirmed entry once the post succeeded, or by a failed one if Expensify surely did not handle it.
+/// Expenses of pending entries are left out unless `repost_pending` is set.
+fn post_and_record(
+    context_dir: &std::path::Path,
+    client: &expensify::Client,
+    mode: Mode,
+    cmd: expend::Command,
+    undoes: Option<usize>,
+    repost_pending: bool,
+) -> Result<(), Error> {
+    let timestamp = Utc::now();
+    let context_name = cmd.context().map(|ctx| ctx.name.clone());
+    let kind = cmd.kind();
+    let entry = |payload_type: &str, payload, records, response, pending| expend::ledger::Entry {
+        timestamp,
+        context: context_name.clone(),
+        kind: kind.to_owned(),
+        payload_type: payload_type.to_owned(),
+        payload,
+        response,
+        records,
+        undoes,
+        pending,
+        failed: false,
+    };
+    let ledger = expend::ledger::read(&context_dir.join(expend::ledger::FILE_NAME))?;
+    let mut pending = None;
+    let posted = expend::execute(
+        client,
+        cmd,
+        &expend::ledger::external_ids(&ledger, !repost_pending),
+        |type_name, value, notes, records| {
+            confirm_payload(mode, type_name, value, notes)?;
+            let entry = entry(
+                type_name,
+                value.clone(),
+                records.to_vec(),
+                serde_json::Value::Null,
+                true,
+            );
+            history::record(context_dir, &entry)?;
+            pending = Some(entry);
+            Ok(())
+        },
+    );
+    let posted = match (posted, pending) {
+        (Ok(posted), _) => posted,
+        (Err(err), Some(pending)) => {
+            if expensify::had_no_effect(&err) {
+                history::record(
+                    context_dir,
+                    &expend::ledger::Entry {
+                        pending: false,
+                        failed: true,
+                        ..pending
+                    },
+                )?;
+            }
+            return Err(err);
+        }
+        (Err(err), None) => return Err(err),
+    };
+    show_value(posted.response.clone())?;
+    history::record(
+        context_dir,
+        &entry(
+            &posted.payload_type,
+            posted.payload,
+            posted.records,
+            posted.response,
+            false,
+        ),
+    )
+}
+
 fn run() -> Result<(), Error> {
     use structopt::StructOpt;
     let opt: Options = Options::from_args();
@@ -86,40 +256,7 @@ fn run() -> Result<(), Error> {
             }).map(|_| ())?
         }
         Options::Post(post) => {
-            let (user, secret) = match (&post.user_id, &post.user_secret) {
-                (Some(ref user), Some(ref secret)) => (user.to_owned(), secret.to_owned()),
-                (Some(_), None) => {
-                    exit_with("Please provide the secret as well with --user-secret.")
-                }
-                (None, Some(_)) => exit_with("Please provide the user as well with --user-id."),
-                (None, None) => match if post.no_keychain {
-                    None
-                } else {
-                    let creds = credentials::from_keychain_or_clear(post.clear_keychain_entry)?;
-                    eprintln!("Using Expensify credentials from keychain.");
-                    creds
-                } {
-                    Some(creds) => creds,
-                    None => credentials::query_from_user().and_then(|creds| {
-                        if post.no_keychain {
-                            Ok(creds)
-                        } else {
-                            eprintln!(
-                                "Storing credentials in keychain - use --no-keychain to disable."
-                            );
-                            credentials::store_in_keychain(creds)
-                        }
-                    })?,
-                },
-            };
-
-            let mode = match (post.dry_run, post.yes) {
-                (true, true) => exit_with("--auto-confirm and --dry-run are mutually exclusive."),
-                (true, false) => Mode::DryRun,
-                (false, true) => Mode::AutoConfirm,
-                (false, false) => Mode::Confirm,
-            };
-
+            let (client, mode) = client_and_mode(&post.posting)?;
             let context_dir = context::into_directory_path(post.context_from)?;
 
             let cmd = match post.cmd {
@@ -128,39 +265,120 @@ fn run() -> Result<(), Error> {
                     time_period,
                     kind,
                     subtract,
+                    meals,
+                    meals_on,
+                    ignore_three_month_rule,
+                    destination_on,
                     comment,
                 } => {
-                    let context =
-                        context::from_file_path(&context::file_path(&context_dir, &context))?;
-                    let context = expend::Context {
-                        user: context,
-                        reference_date: post.weekdate,
-                        comment,
-                    };
+                    let context = with_destinations(
+                        context::load(
+                            &context_dir,
+                            &context,
+                            post.weekdate,
+                            comment,
+                            post.include_holidays,
+                        )?,
+                        destination_on,
+                        None,
+                    )?;
+                    let context = with_meals(context, meals_on, None)?;
                     let time_period: expend::TimePeriod = time_period.parse()?;
                     let kind: expend::perdiem::Kind = kind.parse()?;
+                    let meals = parse_meals(meals)?;
                     let mode = if subtract {
                         expend::perdiem::Mode::Subtract
                     } else {
                         expend::perdiem::Mode::Add
                     };
-                    expend::Command::PerDiem(context, time_period, kind, mode)
+                    expend::Command::PerDiem(
+                        context,
+                        time_period,
+                        kind,
+                        mode,
+                        meals,
+                        ignore_three_month_rule,
+                    )
+                }
+                PostSubcommands::Trip {
+                    context,
+                    departure,
+                    returned,
+                    no_overnight_stay,
+                    meals,
+                    meals_on,
+                    ignore_three_month_rule,
+                    destination_on,
+                    comment,
+                } => {
+                    let trip = Trip::new(departure, returned, !no_overnight_stay)?;
+                    let mut context = context::load(&context_dir, &context, None, comment, true)?;
+                    // Periods like 'tue' refer to the week of the trip.
+                    context.reference_date = Some(trip.first_day());
+                    let context = with_destinations(context, destination_on, Some(&trip))?;
+                    let context = with_meals(context, meals_on, Some(&trip))?;
+                    expend::Command::Trip(
+                        context,
+                        trip,
+                        parse_meals(meals)?,
+                        ignore_three_month_rule,
+                    )
+                }
+                PostSubcommands::Accommodation {
+                    context,
+                    time_period,
+                    destination_on,
+                    comment,
+                } => {
+                    let context = with_destinations(
+                        context::load(&context_dir, &context, post.weekdate, comment, true)?,
+                        destination_on,
+                        None,
+                    )?;
+                    let time_period: expend::TimePeriod = time_period.parse()?;
+                    expend::Command::Accommodation(context, time_period)
+                }
+                PostSubcommands::HomeOffice {
+                    context,
+                    time_period,
+                    exceed_yearly_maximum,
+                    comment,
+                } => {
+                    let context = context::load(
+                        &context_dir,
+                        &context,
+                        post.weekdate,
+                        comment,
+                        post.include_holidays,
+                    )?;
+                    let time_period: expend::TimePeriod = time_period.parse()?;
+                    expend::Command::HomeOffice(context, time_period, exceed_yearly_maximum)
+                }
+                PostSubcommands::Mileage {
+                    context,
+                    time_period,
+                    distance,
+                    comment,
+                } => {
+                    let context = context::load(
+                        &context_dir,
+                        &context,
+                        post.weekdate,
+                        comment,
+                        post.include_holidays,
+                    )?;
+                    let time_period: expend::TimePeriod = time_period.parse()?;
+                    expend::Command::Mileage(context, time_period, distance)
                 }
                 PostSubcommands::FromFile {
                     context,
                     payload_type,
                     input,
                 } => {
-                    let context = context.map(|c| context::file_path(&context_dir, &c));
                     let context = match context {
-                        Some(file) => Some(context::from_file_path(&file)?),
+                        Some(name) => Some(context::load(&context_dir, &name, None, None, true)?),
                         None => None,
-                    }
-                    .map(|ctx| expend::Context {
-                        user: ctx,
-                        reference_date: None,
-                        comment: None,
-                    });
+                    };
                     let json_value: serde_json::Value =
                         serde_yaml::from_reader(std::fs::File::open(&input).with_context(
                             |_| format!("Failed to open file at '{}'", input.display()),
@@ -169,10 +387,30 @@ fn run() -> Result<(), Error> {
                 }
             };
 
-            expend::execute(user, secret, cmd, |type_name, value| {
-                confirm_payload(mode, type_name, value)
-            })
-            .and_then(show_value)?
+            post_and_record(&context_dir, &client, mode, cmd, None, post.repost_pending)?
+        }
+        Options::Undo(undo) => {
+            let context_dir = context::into_directory_path(undo.context_from)?;
+            let entry = history::entry_to_undo(&context_dir, undo.entry)?;
+            let (client, mode) = client_and_mode(&undo.posting)?;
+            let context = match entry.context {
+                Some(ref name) if context::file_path(&context_dir, name).is_file() => {
+                    Some(context::load(&context_dir, name, None, None, true)?)
+                }
+                _ => None,
+            };
+            post_and_record(
+                &context_dir,
+                &client,
+                mode,
+                expend::Command::Undo(context, undo.entry, entry),
+                Some(undo.entry),
+                false,
+            )?
+        }
+        Options::History(history) => {
+            let directory = context::into_directory_path(history.context_from.clone())?;
+            history::handle(&directory, history)?
         }
         Options::Context(Context { from, cmd }) => {
             context::handle(from, cmd)?;
@@ -181,6 +419,42 @@ fn run() -> Result<(), Error> {
     })
 }
 
+/// The exit code for `err`, along with a hint on how to resolve it, if there is one.
+fn exit_code_and_hint(err: &Error) -> (i32, Option<&'static str>) {
+    use expensify::Error::*;
+    match err
+        .iter_chain()
+        .filter_map(|cause| cause.downcast_ref::<expensify::Error>())
+        .next()
+    {
+        Some(Authentication { .. }) => (
+            3,
+            Some("Use --clear-keychain-entry to enter new credentials, which can be generated with 'expend authenticate'."),
+        ),
+        Some(Validation { .. }) => (
+            4,
+            Some("Use --dry-run to review the payload - when posting from a file, make sure it is accepted by the Expensify integration server."),
+        ),
+        Some(RateLimit { .. }) => (
+            5,
+            Some("Please wait a few minutes before trying again."),
+        ),
+        Some(Server { .. }) => (
+            6,
+            Some("This is probably temporary - please try again later."),
+        ),
+        Some(Unexpected { .. }) => (7, None),
+        None => (1, None),
+    }
+}
+
 fn main() {
-    ok_or_exit(run())
+    if let Err(err) = run() {
+        let (code, hint) = exit_code_and_hint(&err);
+        print_causes(err, stderr());
+        if let Some(hint) = hint {
+            eprintln!("\nHint: {}", hint);
+        }
+        std::process::exit(code)
+    }
 }
@@ -1,6 +1,4 @@
-extern crate chrono;
-
-use self::chrono::{Date, Utc};
+use chrono::{DateTime, Utc};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -14,6 +12,12 @@ pub enum Options {
     #[structopt(name = "context", alias = "contexts")]
     /// Interact with contexts - one or more sets of properties that are shared across many sub-commands
     Context(Context),
+    #[structopt(name = "undo")]
+    /// Revert a post from the ledger by posting the same expenses with negated amounts.
+    Undo(Undo),
+    #[structopt(name = "history")]
+    /// Show what was posted successfully, as recorded in the ledger in the context directory.
+    History(History),
     #[structopt(name = "authenticate")]
     /// Authenticate yourself towards Expensify and store the obtained credentials in your keychain.
     /// This is done for you the first time you try to post if no stored credentials are found.
@@ -22,6 +26,38 @@ pub enum Options {
 
 #[derive(StructOpt)]
 pub struct Post {
+    #[structopt(flatten)]
+    pub posting: Posting,
+
+    #[structopt(parse(from_os_str), long = "context-dir")]
+    /// The directory from which to load contexts.
+    /// Defaults to your <OS config dir>/expend-rs
+    pub context_from: Option<PathBuf>,
+
+    #[structopt(long = "weekdate", alias = "w")]
+    /// The date of a day in the week that your per-diem dates should assume, formatted
+    /// like 2018-09-25. It may also be 'today', 'yesterday', 'last-week', 'next-week',
+    /// relative to today like '-2w' or '+3d', or an ISO week like 2018-W39.
+    /// Today is determined using the time zone of your context.
+    pub weekdate: Option<String>,
+
+    #[structopt(long = "include-holidays")]
+    /// If set, holidays of your context will not be left out of weekdays and ranges of days.
+    pub include_holidays: bool,
+
+    #[structopt(long = "repost-pending")]
+    /// If set, expenses of posts which did not complete, like when timing out, are posted again.
+    /// Use it only after making sure on Expensify that these expenses were not created.
+    /// See the 'history' sub-command for posts with unknown outcome.
+    pub repost_pending: bool,
+
+    #[structopt(subcommand)]
+    pub cmd: PostSubcommands,
+}
+
+// Flags shared by all sub-commands sending something to Expensify.
+#[derive(StructOpt)]
+pub struct Posting {
     #[structopt(long = "user-id", short = "u")]
     /// The user id, see https://integrations.expensify.com/Integration-Server/doc/#authentication
     pub user_id: Option<String>,
@@ -46,23 +82,14 @@ pub struct Post {
     #[structopt(long = "clear-keychain-entry")]
     /// If set, the previously stored credentials will be cleared. This is useful if your credentials change.
     pub clear_keychain_entry: bool,
-
-    #[structopt(parse(from_os_str), long = "context-dir")]
-    /// The directory from which to load contexts.
-    /// Defaults to your <OS config dir>/expend-rs
-    pub context_from: Option<PathBuf>,
-
     #[structopt(
-        parse(try_from_str = "expend::from_date_string"),
-        long = "weekdate",
-        alias = "w"
+        long = "endpoint",
+        env = "EXPEND_ENDPOINT",
+        parse(try_from_str = "expend::expensify::Url::parse")
     )]
-    /// The date of a day in the week that your per-diem dates should assume, formatted
-    /// like 2018-09-25.
-    pub weekdate: Option<Date<Utc>>,
-
-    #[structopt(subcommand)]
-    pub cmd: PostSubcommands,
+    /// The base url of the Expensify integration server, like https://integrations.expensify.com.
+    /// Useful to post to a local stand-in server for testing.
+    pub endpoint: Option<expend::expensify::Url>,
 }
 
 #[derive(StructOpt)]
@@ -82,12 +109,20 @@ pub enum PostSubcommands {
         ///     - the given singular day, either as shorthand or full identifier. Case-insensitive|
         /// |<day>,<day>[,<day>...]
         ///     - <day> can be any day like Mon or Monday. Days will be unified, and ordered, thus
-        ///       duplicate and out-of-order days can not be expressed as they will be fixed automatically.
+        ///       duplicate and out-of-order days can not be expressed as they will be fixed automatically.|
+        /// |<days>[,<days>...] [except <days>[,<days>...]]
+        ///     - <days> can be a day, a range like mon-wed or 'weekdays', like in 'mon-wed,fri' or 'weekdays except wed'.
+        ///       Days after 'except' are left out.|
+        /// |month OR <year>-<month> OR weeks:<count> [<days>]
+        ///     - the weekdays of the current month, of a month like 2018-10 or of a number of weeks starting with the current one.
+        ///       It may be followed by the days to use instead, like in 'month mon-thu'.|
+        /// |<date>
+        ///     - a single date like 2018-09-27, independent of the current week|
+        /// |<date>..<date>
+        ///     - all days from the first to the last date, inclusive, like 2018-09-27..2018-10-02. It may span weeks.|
         time_period: String,
 
-        #[structopt(raw(
-            possible_values = r#"&["breakfast", "fullday", "arrival", "departure", "daytrip", "lunch", "dinner"]"#
-        ))]
+        #[structopt(raw(possible_values = "expend::perdiem::Kind::NAMES"))]
         /// The kind of per diem you need.
         kind: String,
 
@@ -96,11 +131,132 @@ pub enum PostSubcommands {
         /// and subtract individual lunches or dinners.
         subtract: bool,
 
+        #[structopt(long = "meals")]
+        /// The meals provided on each day, like 'breakfast' or 'breakfast,dinner'. Each of these is deducted
+        /// from the per-diem as a percentage of the full-day rate of your destination: 20% for breakfast,
+        /// and 40% for lunch and dinner each. The deduction is posted as separate expense.
+        meals: Option<String>,
+
+        #[structopt(long = "meals-on")]
+        /// The meals provided on some days, overriding --meals, like 'mon-tue=breakfast,dinner' or '2018-09-28=none'.
+        /// The days can be given like the time period. Can be used multiple times, and later ones win.
+        meals_on: Vec<String>,
+
+        #[structopt(long = "ignore-three-month-rule")]
+        /// If set, per-diems are posted even if they are outside of the first three months at the same workplace,
+        /// which are determined from the days posted with the same context and project in the local record.
+        /// The three months start anew after an interruption of at least four weeks.
+        ignore_three_month_rule: bool,
+
+        #[structopt(long = "destination-on")]
+        /// The destination of some days, overriding the one of your context, like '2018-09-26..2018-09-27=India-Other'
+        /// or 'fri=home', where 'home' is the country of your context. The days can be given like the time period.
+        /// Can be used multiple times, and later ones win.
+        destination_on: Vec<String>,
+
         #[structopt(long = "comment", short = "m")]
         /// The comment to be used. It should explain the purpose of the per-diem.
         /// If the time period is not a single day, the comment will be added as suffix to comment generated using the dates '<from> to <to>'.
         comment: Option<String>,
     },
+    #[structopt(name = "trip")]
+    /// Post all per-diems of a business trip, deriving the kind of each day from when you left and came back
+    Trip {
+        #[structopt(long = "context", short = "c", default_value = "default")]
+        /// The name of the context to use.
+        context: String,
+
+        #[structopt(parse(try_from_str = "expend::from_date_time_string"))]
+        /// When you left home or the office, formatted like 2018-09-24T07:30
+        departure: DateTime<Utc>,
+
+        #[structopt(name = "return", parse(try_from_str = "expend::from_date_time_string"))]
+        /// When you came back home or to the office, formatted like 2018-09-26T19:00
+        returned: DateTime<Utc>,
+
+        #[structopt(long = "no-overnight-stay")]
+        /// If set, the trip passed midnight without staying anywhere overnight. It is then filed as single day trip
+        /// on the day you spent more time on, if it lasted more than 8 hours.
+        no_overnight_stay: bool,
+
+        #[structopt(long = "meals")]
+        /// The meals provided on each day of the trip. See the 'per-diem' sub-command for details.
+        /// Breakfast is not deducted on the day of departure of trips with an overnight stay, unless given with --meals-on.
+        meals: Option<String>,
+
+        #[structopt(long = "meals-on")]
+        /// The meals provided on some days of the trip, overriding --meals. See the 'per-diem' sub-command for details.
+        meals_on: Vec<String>,
+
+        #[structopt(long = "ignore-three-month-rule")]
+        /// If set, per-diems are posted even if they are outside of the first three months at the same workplace.
+        /// See the 'per-diem' sub-command for details.
+        ignore_three_month_rule: bool,
+
+        #[structopt(long = "destination-on")]
+        /// The destination of some days of the trip. See the 'per-diem' sub-command for details.
+        destination_on: Vec<String>,
+
+        #[structopt(long = "comment", short = "m")]
+        /// The comment to be used. It should explain the purpose of the trip.
+        comment: Option<String>,
+    },
+    #[structopt(name = "accommodation", alias = "overnight")]
+    /// Post the overnight flat rate for nights without a booked hotel, relative to the current week, by default
+    Accommodation {
+        #[structopt(long = "context", short = "c", default_value = "default")]
+        /// The name of the context to use.
+        context: String,
+
+        /// The days after which you stayed overnight. See the 'per-diem' sub-command for valid values.
+        time_period: String,
+
+        #[structopt(long = "destination-on")]
+        /// The destination of some nights. See the 'per-diem' sub-command for details.
+        destination_on: Vec<String>,
+
+        #[structopt(long = "comment", short = "m")]
+        /// The comment to be used. It should explain the purpose of the stay.
+        /// If the time period is not a single day, the comment will be added as suffix to comment generated using the dates '<from> to <to>'.
+        comment: Option<String>,
+    },
+    #[structopt(name = "home-office", alias = "homeoffice")]
+    /// Post the home-office flat rate for days worked from home, relative to the current week, by default
+    HomeOffice {
+        #[structopt(long = "context", short = "c", default_value = "default")]
+        /// The name of the context to use.
+        context: String,
+
+        /// The days you worked from home. See the 'per-diem' sub-command for valid values.
+        time_period: String,
+
+        #[structopt(long = "exceed-yearly-maximum")]
+        /// If set, days are posted even if the yearly maximum of the home-office flat rate is exceeded.
+        /// The days posted so far are taken from the record in the context directory.
+        exceed_yearly_maximum: bool,
+
+        #[structopt(long = "comment", short = "m")]
+        /// The comment to be used for each day.
+        comment: Option<String>,
+    },
+    #[structopt(name = "mileage")]
+    /// Post mileage for driving your own car, relative to the current week, by default
+    Mileage {
+        #[structopt(long = "context", short = "c", default_value = "default")]
+        /// The name of the context to use.
+        context: String,
+
+        /// The days on which you drove. See the 'per-diem' sub-command for valid values.
+        time_period: String,
+
+        /// The distance driven on each day, in the unit used by the country of your context, like kilometers or miles.
+        distance: u32,
+
+        #[structopt(long = "comment", short = "m")]
+        /// The comment to be used. It should explain the purpose of the trip.
+        /// If the time period is not a single day, the comment will be added as suffix to comment generated using the dates '<from> to <to>'.
+        comment: Option<String>,
+    },
     #[structopt(name = "from-file")]
     /// Load a file with structured data and use it as payload.
     FromFile {
@@ -118,6 +274,55 @@ pub enum PostSubcommands {
     },
 }
 
+#[derive(StructOpt)]
+pub struct Undo {
+    #[structopt(flatten)]
+    pub posting: Posting,
+
+    #[structopt(parse(from_os_str), long = "context-dir")]
+    /// The directory containing the ledger and contexts.
+    /// Defaults to your <OS config dir>/expend-rs
+    pub context_from: Option<PathBuf>,
+
+    /// The number of the post to revert, as shown by the 'history' sub-command.
+    pub entry: usize,
+}
+
+#[derive(StructOpt)]
+pub struct History {
+    #[structopt(parse(from_os_str), long = "context-dir")]
+    /// The directory containing the ledger.
+    /// Defaults to your <OS config dir>/expend-rs
+    pub context_from: Option<PathBuf>,
+
+    #[structopt(long = "from")]
+    /// Only show expenses dated on or after this date, like 2018-09-25. Relative dates like '-2w' work as well,
+    /// see '--weekdate' of the 'post' sub-command. Today is determined using the time zone of the context given
+    /// with '--context', or of the default context.
+    pub from: Option<String>,
+
+    #[structopt(long = "to")]
+    /// Only show expenses dated on or before this date. See '--from' for valid values.
+    pub to: Option<String>,
+
+    #[structopt(long = "context", short = "c")]
+    /// Only show posts made with the context of the given name.
+    pub context: Option<String>,
+
+    #[structopt(long = "kind", short = "k")]
+    #[structopt(raw(possible_values = "expend::Command::KINDS"))]
+    /// Only show posts of the given kind.
+    pub kind: Option<String>,
+
+    #[structopt(long = "min-amount")]
+    /// Only show expenses with at least this amount, like '24' or '4.80'. Negative amounts are possible too.
+    pub min_amount: Option<String>,
+
+    #[structopt(long = "max-amount")]
+    /// Only show expenses with at most this amount. See '--min-amount' for valid values.
+    pub max_amount: Option<String>,
+}
+
 #[derive(StructOpt)]
 pub struct Context {
     #[structopt(parse(from_os_str), long = "from", alias = "at")]
@@ -142,13 +347,14 @@ pub enum ContextSubcommand {
         name: String,
 
         #[structopt(long = "country", short = "c", default_value = "Germany")]
-        #[structopt(raw(possible_values = r#"&["Germany"]"#))]
-        /// The name of the country you are in. It's used to identify your currency and currency symbol.
+        #[structopt(raw(possible_values = "expend::context::Country::NAMES"))]
+        /// The name of the country you are in. It's used to identify your currency, currency symbol
+        /// as well as domestic per-diem and mileage rates.
         country: String,
 
         #[structopt(long = "destination", short = "d")]
-        #[structopt(raw(possible_values = r#"&["India-Other"]"#))]
-        /// If set, this should be a known destination which is relative to the country set with --country.
+        /// If set, this should be a known destination which is relative to the country set with --country,
+        /// like 'India-Other', 'UK-London' or 'USA-New York'. Cities without their own rates use '<Country>-Other'.
         /// If unset, it will default to your country.
         destination: Option<String>,
 
@@ -174,6 +380,37 @@ pub enum ContextSubcommand {
         )]
         /// The name of the per-diems category. It will be used to populate the category field in the per-diems subcommand.
         category_per_diems_name: String,
+
+        #[structopt(
+            long = "category-mileage-name",
+            default_value = "Mileage/Parking/Tolls"
+        )]
+        /// The name of the mileage category. It will be used to populate the category field in the mileage subcommand.
+        category_mileage_name: String,
+
+        #[structopt(long = "category-accommodation-name", default_value = "Lodging")]
+        /// The name of the accommodation category. It will be used to populate the category field in the accommodation subcommand.
+        category_accommodation_name: String,
+
+        #[structopt(long = "category-home-office-name", default_value = "Home Office")]
+        /// The name of the home-office category. It will be used to populate the category field in the home-office subcommand.
+        category_home_office_name: String,
+
+        #[structopt(long = "time-zone")]
+        /// Your offset from UTC, like '+05:30' or 'UTC-8'. It determines which day it is today. Defaults to UTC.
+        time_zone: Option<String>,
+
+        #[structopt(long = "week-start")]
+        /// The first day of your week, like 'sunday'. The 'weekdays' period covers the first five days of the week.
+        /// Defaults to Monday.
+        week_start: Option<String>,
+
+        #[structopt(long = "holidays")]
+        /// The holidays to leave out of weekdays and ranges of days. It may be the code of a German federal state,
+        /// like 'BY' for Bavaria, or the path to a file with holidays. Files ending in '.ics' are read as calendar,
+        /// any other file as YAML list of items with 'date' and 'name', like '- {date: 2018-12-24, name: Christmas Eve}'.
+        /// Can be given multiple times.
+        holidays: Vec<String>,
     },
 
     #[structopt(name = "get")]
@@ -0,0 +1,103 @@
+use chrono::{Date, Utc};
+use perdiem::{Kind, KIND_PREFIX};
+use record::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use to_date_string;
+use Context;
+
+/// The per-diems of one kind on one day.
+#[derive(Default)]
+struct Claims {
+    /// Entries with positive amounts count up, negative ones, like reductions or reverted posts, count down.
+    count: i32,
+    amount: i32,
+    on_record: bool,
+}
+
+/// Find conflicts of the `claimed` per-diems with each other and with all per-diems in the local record,
+/// which are several daily per-diems or the same kind of per-diem more than once on a day, and meal reductions
+/// exceeding the per-diems of their day.
+pub(crate) fn check(ctx: &Context, claimed: &[Entry]) -> Vec<String> {
+    let claimed_days: BTreeSet<_> = claimed.iter().map(|e| e.date).collect();
+    let mut days: BTreeMap<Date<Utc>, BTreeMap<&str, Claims>> = BTreeMap::new();
+    let recorded = ctx.record.entries().iter().map(|e| (e, true));
+    for (entry, on_record) in recorded.chain(claimed.iter().map(|e| (e, false))) {
+        if !claimed_days.contains(&entry.date) || !entry.kind.starts_with(KIND_PREFIX) {
+            continue;
+        }
+        let claims = days
+            .entry(entry.date)
+            .or_insert_with(BTreeMap::new)
+            .entry(&entry.kind[KIND_PREFIX.len()..])
+            .or_insert_with(Claims::default);
+        claims.count += entry.amount.signum();
+        claims.amount += entry.amount;
+        claims.on_record |= on_record;
+    }
+
+    let currency = ctx.user.country.currency();
+    let mut findings = Vec::new();
+    for (date, kinds) in days {
+        let positive: Vec<_> = kinds.iter().filter(|(_, c)| c.count > 0).collect();
+        let num_daily: i32 = positive
+            .iter()
+            .filter(|(kind, _)| is_daily(kind))
+            .map(|(_, c)| c.count)
+            .sum();
+        if num_daily > 1 || positive.iter().any(|(_, c)| c.count > 1) {
+            findings.push(format!(
+                "Conflicting per-diems on {}: {}{}.",
+                to_date_string(&date),
+                positive
+                    .iter()
+                    .map(|(kind, c)| if c.count > 1 {
+                        format!("{} ({} times)", kind, c.count)
+                    } else {
+                        kind.to_string()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if positive.iter().any(|(_, c)| c.on_record) {
+                    ", including posts in the local record"
+                } else {
+                    ""
+                }
+            ));
+        }
+        let per_diems: i32 = kinds
+            .iter()
+            .filter(|(kind, _)| !is_meal(kind))
+            .map(|(_, c)| c.amount)
+            .sum();
+        let reductions: i32 = kinds
+            .iter()
+            .filter(|(kind, c)| is_meal(kind) && c.amount < 0)
+            .map(|(_, c)| c.amount)
+            .sum();
+        if reductions < 0 && -reductions > per_diems {
+            findings.push(format!(
+                "The meal reductions of {} on {} exceed the per-diems of {} known for that day.",
+                currency.format(-reductions),
+                to_date_string(&date),
+                currency.format(per_diems)
+            ));
+        }
+    }
+    findings
+}
+
+/// Whether `kind` names a meal, which is subtracted from daily per-diems, or deductions for provided meals.
+fn is_meal(kind: &str) -> bool {
+    match kind.parse() {
+        Ok(Kind::Breakfast) | Ok(Kind::Lunch) | Ok(Kind::Dinner) => true,
+        _ => kind == "meals",
+    }
+}
+
+/// Whether `kind` names a per-diem for a whole day, of which there can only be one per day.
+fn is_daily(kind: &str) -> bool {
+    match kind.parse() {
+        Ok(Kind::FullDay) | Ok(Kind::Arrival) | Ok(Kind::Departure) | Ok(Kind::Daytrip) => true,
+        _ => false,
+    }
+}
@@ -1,10 +1,85 @@
 use chrono::prelude::*;
 use failure::Error;
+use holidays::Holidays;
+use perdiem::Meals;
+use rates::RateTable;
+use record::Record;
+use serde;
+use std::collections::{BTreeMap, BTreeSet};
 use time::Duration;
+use TimePeriod;
+use Weekday;
 
-#[derive(Serialize, Deserialize)]
-pub enum Country {
-    Germany,
+/// Declares all home countries along with everything that is derived from them.
+/// Adding a country here makes it available everywhere, including the command-line.
+macro_rules! countries {
+    ($($country:ident => ($name:expr, $currency:ident)),* $(,)*) => {
+        #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Country {
+            $($country),*
+        }
+
+        impl Country {
+            /// The names of all countries, as accepted by `from_str()`.
+            pub const NAMES: &'static [&'static str] = &[$($name),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Country::$country => $name),*
+                }
+            }
+
+            pub fn currency(&self) -> Currency {
+                match self {
+                    $(Country::$country => Currency::$currency),*
+                }
+            }
+
+            fn all() -> &'static [Country] {
+                &[$(Country::$country),*]
+            }
+        }
+    };
+}
+
+/// Declares all currencies along with their symbol.
+macro_rules! currencies {
+    ($($currency:ident => $symbol:expr),* $(,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Currency {
+            $($currency),*
+        }
+
+        impl Currency {
+            pub fn symbol(&self) -> &'static str {
+                match self {
+                    $(Currency::$currency => $symbol),*
+                }
+            }
+        }
+
+        impl std::fmt::Display for Currency {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+                match self {
+                    $(Currency::$currency => f.write_str(stringify!($currency))),*
+                }
+            }
+        }
+    };
+}
+
+countries! {
+    Germany => ("Germany", EUR),
+    India => ("India", INR),
+    UK => ("UK", GBP),
+    USA => ("USA", USD),
+}
+
+currencies! {
+    EUR => "€",
+    INR => "₹",
+    GBP => "£",
+    USD => "$",
 }
 
 impl Default for Country {
@@ -13,36 +88,82 @@ impl Default for Country {
     }
 }
 
-pub enum Currency {
-    EUR,
-}
-
 impl std::str::FromStr for Country {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, <Self as std::str::FromStr>::Err> {
-        use self::Country::*;
-        match s {
-            "germany" | "Germany" => Ok(Germany),
-            _ => bail!(
-                "Invalid country identifier: '{}'. Country is not implemented.",
-                s
+        match Country::all()
+            .iter()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+        {
+            Some(c) => Ok(*c),
+            None => bail!(
+                "Invalid country identifier: '{}'. Valid countries are {}.",
+                s,
+                Country::NAMES.join(", ")
             ),
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum Destination {
-    IndiaOther,
+impl std::fmt::Display for Country {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(self.name())
+    }
+}
+
+impl Currency {
+    /// Format the given amount of cents for display in a merchant, like '€1,234.50'.
+    /// Indian rupees are grouped in lakhs and crores, like '₹1,23,456.00'.
+    pub fn format(&self, cents: i32) -> String {
+        let units = (cents.abs() / 100).to_string();
+        let (head, tail) = units.split_at(units.len().saturating_sub(3));
+        let group_size = match self {
+            Currency::INR => 2,
+            _ => 3,
+        };
+        let mut groups = Vec::new();
+        let mut head = head;
+        while !head.is_empty() {
+            let (rest, group) = head.split_at(head.len().saturating_sub(group_size));
+            groups.insert(0, group);
+            head = rest;
+        }
+        groups.push(tail);
+        format!(
+            "{}{}{}.{:02}",
+            self.symbol(),
+            if cents < 0 { "-" } else { "" },
+            groups.join(","),
+            cents.abs() % 100
+        )
+    }
+}
+
+/// A destination abroad as named in the per-diem rates, like 'India-Other' or 'USA-New York'.
+/// Parsed names are checked against the rates of a context with `RateTable::destination()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination(pub(crate) String);
+
+/// Names compare equal regardless of case, dashes and whitespace, which also accepts 'IndiaOther'
+/// as written by previous versions.
+pub(crate) fn normalized(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl Destination {
+    /// Whether both name the same destination, regardless of case, dashes and whitespace.
+    pub fn matches(&self, other: &Destination) -> bool {
+        normalized(&self.0) == normalized(&other.0)
+    }
 }
 
 impl std::fmt::Display for Destination {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        use self::Destination::*;
-        match self {
-            IndiaOther => f.write_str("India-Other"),
-        }
+        f.write_str(&self.0)
     }
 }
 
@@ -50,50 +171,105 @@ impl std::str::FromStr for Destination {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, <Self as std::str::FromStr>::Err> {
-        match s {
-            "India-Other" | "india-other" => Ok(Destination::IndiaOther),
-            _ => bail!(
-                "Invalid destination for Germany: '{}'. Destination is not implemented.",
-                s
-            ),
+        let name = s.trim();
+        if name.is_empty() {
+            bail!("Invalid destination: ''. Destinations are named like 'India-Other' or 'USA-New York'.");
         }
+        Ok(Destination(name.to_owned()))
     }
 }
 
-impl std::fmt::Display for Country {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        use self::Country::*;
-        match self {
-            Germany => f.write_str("Germany"),
-        }
+impl serde::Serialize for Destination {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
     }
 }
 
-impl std::fmt::Display for Currency {
+impl<'de> serde::Deserialize<'de> for Destination {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A fixed offset from UTC, used to determine the current date of the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcOffset(FixedOffset);
+
+impl UtcOffset {
+    pub fn today(&self) -> Date<Utc> {
+        Date::from_utc(Utc::now().with_timezone(&self.0).date().naive_local(), Utc)
+    }
+}
+
+impl Default for UtcOffset {
+    fn default() -> Self {
+        UtcOffset(FixedOffset::east(0))
+    }
+}
+
+impl std::fmt::Display for UtcOffset {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        use self::Currency::*;
-        match self {
-            EUR => f.write_str("EUR"),
-        }
+        let seconds = self.0.local_minus_utc();
+        write!(
+            f,
+            "UTC{}{:02}:{:02}",
+            if seconds < 0 { '-' } else { '+' },
+            seconds.abs() / 3600,
+            seconds.abs() % 3600 / 60
+        )
     }
 }
 
-impl Currency {
-    pub fn symbol(&self) -> &'static str {
-        use self::Currency::*;
-        match self {
-            EUR => "€",
+impl std::str::FromStr for UtcOffset {
+    type Err = Error;
+
+    /// Parses offsets like 'UTC', '+05:30', 'UTC+5:30', '-0800' or '-8'.
+    fn from_str(s: &str) -> Result<Self, <Self as std::str::FromStr>::Err> {
+        let invalid = || {
+            format_err!(
+                "Invalid time zone '{}' - use an offset from UTC like '+05:30' or 'UTC-8'",
+                s
+            )
+        };
+        let offset = s.trim();
+        let offset = match offset.get(..3) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("utc") => &offset[3..],
+            _ => offset,
+        };
+        if offset.is_empty() {
+            return Ok(UtcOffset::default());
         }
+        let (sign, offset) = match offset.chars().next() {
+            Some('+') => (1, &offset[1..]),
+            Some('-') => (-1, &offset[1..]),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = match (offset.find(':'), offset.len()) {
+            (Some(pos), _) => (&offset[..pos], &offset[pos + 1..]),
+            (None, 4) => offset.split_at(2),
+            (None, _) => (offset, "0"),
+        };
+        let (hours, minutes): (i32, i32) = match (hours.parse(), minutes.parse()) {
+            (Ok(h), Ok(m)) if h <= 14 && m < 60 => (h, m),
+            _ => return Err(invalid()),
+        };
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(UtcOffset)
+            .ok_or_else(invalid)
     }
 }
 
-impl Country {
-    pub fn currency(&self) -> Currency {
-        use self::Country::*;
-        use self::Currency::*;
-        match self {
-            Germany => EUR,
-        }
+impl serde::Serialize for UtcOffset {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for UtcOffset {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let offset = String::deserialize(deserializer)?;
+        offset.parse().map_err(serde::de::Error::custom)
     }
 }
 
@@ -109,6 +285,15 @@ pub struct UserContext {
     pub tags: Tags,
     #[serde(default)]
     pub categories: Categories,
+    /// The offset from UTC used to determine today's date. Defaults to UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<UtcOffset>,
+    /// The first day of the week. Defaults to Monday.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub week_start: Option<Weekday>,
+    /// German federal states or paths to files with holidays, see `holidays::Holidays`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holidays: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -117,8 +302,12 @@ pub struct Tags {
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Categories {
     pub per_diems: Category,
+    pub mileage: Category,
+    pub accommodation: Category,
+    pub home_office: Category,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -132,6 +321,15 @@ impl Default for Categories {
             per_diems: Category {
                 name: "Per Diem/Stipend (pre-approved)".to_string(),
             },
+            mileage: Category {
+                name: "Mileage/Parking/Tolls".to_string(),
+            },
+            accommodation: Category {
+                name: "Lodging".to_string(),
+            },
+            home_office: Category {
+                name: "Home Office".to_string(),
+            },
         }
     }
 }
@@ -172,15 +370,152 @@ impl UserContext {
 }
 
 pub struct Context {
+    /// The name the user context was stored under.
+    pub name: String,
     pub user: UserContext,
     pub reference_date: Option<Date<Utc>>,
     pub comment: Option<String>,
+    pub rates: RateTable,
+    /// Holidays to leave out of weekdays and ranges of days.
+    pub holidays: Holidays,
+    /// Destinations of individual days, overriding the one of the user. `None` stands for the home country.
+    pub destinations: BTreeMap<Date<Utc>, Option<Destination>>,
+    /// Meals provided on individual days, overriding the ones given for all days.
+    pub meals: BTreeMap<Date<Utc>, Meals>,
+    /// The days posted so far.
+    pub record: Record,
+    /// The external identifiers of expenses and days posted so far, which are left out of new per-diems.
+    pub posted: BTreeSet<String>,
 }
 
 impl Context {
-    pub fn monday_of_reference_date(&self) -> Result<Date<Utc>, Error> {
-        let d = self.reference_date.unwrap_or_else(Utc::today);
-        d.checked_sub_signed(Duration::days(d.weekday().num_days_from_monday() as i64))
-            .ok_or_else(|| format_err!("Failed to compute Monday from the given date."))
+    /// Today's date in the time zone of the user.
+    pub fn today(&self) -> Date<Utc> {
+        self.user.time_zone.unwrap_or_default().today()
+    }
+
+    /// The first day of the week containing the reference date, or today if unset.
+    /// Weeks start on Monday unless configured otherwise.
+    pub fn start_of_reference_week(&self) -> Result<Date<Utc>, Error> {
+        let d = self.reference_date.unwrap_or_else(|| self.today());
+        let week_start = self.user.week_start.unwrap_or(Weekday::Monday);
+        d.checked_sub_signed(Duration::days(i64::from(
+            (Weekday::of(&d).numerical() + 7 - week_start.numerical()) % 7,
+        )))
+        .ok_or_else(|| format_err!("Failed to compute the start of the week from the given date."))
+    }
+
+    /// The destination on `date`, which is `None` if it is spent in the home country.
+    pub fn destination_on(&self, date: &Date<Utc>) -> Option<&Destination> {
+        match self.destinations.get(date) {
+            Some(destination) => destination.as_ref(),
+            None => self.user.destination.as_ref(),
+        }
+    }
+
+    /// The meals provided on `date`, which are `default` unless overridden for that day.
+    pub fn meals_on(&self, date: &Date<Utc>, default: Meals) -> Meals {
+        *self.meals.get(date).unwrap_or(&default)
+    }
+
+    /// Use the meals of `meals_override` on all of its days, replacing previous overrides.
+    /// Returns the days it applies to.
+    pub fn override_meals(
+        &mut self,
+        meals_override: MealsOverride,
+    ) -> Result<Vec<Date<Utc>>, Error> {
+        let MealsOverride { period, meals } = meals_override;
+        let days = period.into_days(self)?;
+        for day in &days {
+            self.meals.insert(*day, meals);
+        }
+        Ok(days)
+    }
+
+    /// The name of the destination on `date`, or of the home country if it is spent there.
+    pub fn place_on(&self, date: &Date<Utc>) -> String {
+        match self.destination_on(date) {
+            Some(destination) => destination.to_string(),
+            None => self.user.country.to_string(),
+        }
+    }
+
+    /// Use the destination of `destination_override` on all of its days, replacing previous overrides.
+    /// Returns the days it applies to.
+    pub fn override_destination(
+        &mut self,
+        destination_override: DestinationOverride,
+    ) -> Result<Vec<Date<Utc>>, Error> {
+        let DestinationOverride {
+            period,
+            destination,
+        } = destination_override;
+        let destination = match destination {
+            Some(destination) => Some(self.rates.destination(&destination)?),
+            None => None,
+        };
+        let days = period.into_days(self)?;
+        for day in &days {
+            self.destinations.insert(*day, destination.clone());
+        }
+        Ok(days)
+    }
+}
+
+/// The destination of some days, like '2018-09-26..2018-09-28=India-Other' or 'fri=home'.
+/// The days can be given like any time period, and 'home' stands for the home country.
+pub struct DestinationOverride {
+    period: TimePeriod,
+    destination: Option<Destination>,
+}
+
+impl std::str::FromStr for DestinationOverride {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, <Self as std::str::FromStr>::Err> {
+        let pos = s.find('=').ok_or_else(|| {
+            format_err!(
+                "Invalid destination override '{}' - use something like '2018-09-26..2018-09-28=India-Other' or 'fri=home'",
+                s
+            )
+        })?;
+        let destination = s[pos + 1..].trim();
+        Ok(DestinationOverride {
+            period: s[..pos].parse()?,
+            destination: if destination.eq_ignore_ascii_case("home") {
+                None
+            } else {
+                Some(destination.parse()?)
+            },
+        })
+    }
+}
+
+/// The meals provided on some days, like '2018-09-26..2018-09-28=breakfast,lunch' or 'fri=none'.
+/// The days can be given like any time period, and 'none' stands for no meals.
+pub struct MealsOverride {
+    period: TimePeriod,
+    meals: Meals,
+}
+
+impl std::str::FromStr for MealsOverride {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, <Self as std::str::FromStr>::Err> {
+        let pos = s.find('=').ok_or_else(|| {
+            format_err!(
+                "Invalid meals override '{}' - use something like '2018-09-26..2018-09-28=breakfast,lunch' or 'fri=none'",
+                s
+            )
+        })?;
+        let meals = s[pos + 1..].trim();
+        Ok(MealsOverride {
+            period: s[..pos].parse()?,
+            meals: if meals.eq_ignore_ascii_case("none") {
+                Meals::default()
+            } else {
+                meals.parse()?
+            },
+        })
     }
 }
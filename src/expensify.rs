@@ -1,20 +1,125 @@
-use failure::{self, ResultExt};
-use reqwest::{self, Url};
+use chrono::{DateTime, Utc};
+use failure::{self, Fail, ResultExt};
+use record::Entry;
+use reqwest;
+pub use reqwest::Url;
 use serde::Serialize;
 use serde_json as json;
-use std::str::FromStr;
+use std::{
+    cmp,
+    collections::{hash_map::RandomState, BTreeSet},
+    error::Error as StdError,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io,
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-pub const ENDPOINT: &str = "/Integration-Server/ExpensifyIntegrations";
+/// The path of the integration server, relative to the base url of the host.
+pub const ENDPOINT: &str = "Integration-Server/ExpensifyIntegrations";
+/// The name of the field identifying an expense in other systems.
+pub const EXTERNAL_ID: &str = "externalID";
+
+/// The job type creating expenses, which must never be sent twice.
+const CREATE: &str = "create";
 
 pub struct Client {
     host: Url,
     username: String,
     password: String,
+    settings: Settings,
+}
+
+/// How the client deals with slow and failing requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// The longest time to wait for the response to a single request.
+    pub timeout: Duration,
+    /// How often a failed request is retried at most.
+    pub max_retries: u32,
+    /// The time to wait before the first retry, doubled for every retry after that.
+    /// A random part of up to half of it is left out, so clients failing together do not retry together.
+    pub backoff: Duration,
+    /// The longest time to wait before a retry. If Expensify asks to wait longer, the request fails instead.
+    pub max_backoff: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            timeout: Duration::from_secs(30),
+            max_retries: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Whether a failed request may be sent again.
+enum Retry {
+    /// The request was not handled, so it can be sent again after the given time, if known.
+    Safe(Option<Duration>),
+    /// The request may have been handled, so only requests without side effects can be sent again.
+    IfIdempotent,
+    Never,
+}
+
+/// A failure reported by Expensify, classified by the response code so callers can react to it.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Authentication { code: u16, message: String },
+    Validation { code: u16, message: String },
+    RateLimit { code: u16, message: String },
+    Server { code: u16, message: String },
+    Unexpected { code: u16, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        let (what, code, message) = match self {
+            Authentication { code, message } => {
+                ("Expensify rejected the credentials", code, message)
+            }
+            Validation { code, message } => {
+                ("Expensify rejected the request as invalid", code, message)
+            }
+            RateLimit { code, message } => {
+                ("Expensify is limiting the rate of requests", code, message)
+            }
+            Server { code, message } => ("Expensify failed to handle the request", code, message),
+            Unexpected { code, message } => ("Request failed", code, message),
+        };
+        write!(f, "{} with response code {}: {}", what, code, message)
+    }
 }
 
-fn into_err(code: u16, value: json::Value) -> failure::Error {
-    let value_str = json::to_string_pretty(&value).expect("valid json");
-    format_err!("Request failed with http status {}: {}", code, value_str)
+impl Fail for Error {}
+
+impl Error {
+    /// Classify the failed response with the given `code`, which is either the http status or the
+    /// `responseCode` of the body. The message is the `responseMessage` of the body, or the body itself.
+    pub fn from_response(code: u16, value: &json::Value) -> Error {
+        let message = value
+            .get("responseMessage")
+            .and_then(json::Value::as_str)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| json::to_string_pretty(value).expect("valid json"));
+        Error::from_code(code, message)
+    }
+
+    /// Classify a failure by its http status or `responseCode`.
+    pub fn from_code(code: u16, message: String) -> Error {
+        match code {
+            401 | 403 | 407 => Error::Authentication { code, message },
+            429 => Error::RateLimit { code, message },
+            400..=499 => Error::Validation { code, message },
+            500..=599 => Error::Server { code, message },
+            _ => Error::Unexpected { code, message },
+        }
+    }
 }
 
 impl Client {
@@ -23,27 +128,42 @@ impl Client {
         username: impl Into<String>,
         password: impl Into<String>,
     ) -> Client {
+        let mut host = host.unwrap_or_else(|| {
+            Url::from_str("https://integrations.expensify.com").expect("default url to be correct")
+        });
+        // Without a trailing slash, joining the endpoint would replace the last segment of the path.
+        if !host.path().ends_with('/') {
+            let path = format!("{}/", host.path());
+            host.set_path(&path);
+        }
         Client {
-            host: host
-                .unwrap_or_else(|| {
-                    Url::from_str("https://integrations.expensify.com")
-                        .expect("default url to be correct")
-                })
-                .into(),
+            host,
             username: username.into(),
             password: password.into(),
+            settings: Settings::default(),
         }
     }
 
+    pub fn with_settings(self, settings: Settings) -> Client {
+        Client { settings, ..self }
+    }
+
+    /// The url jobs are posted to, keeping any path of the host like 'http://localhost:8080/expensify/'.
+    pub fn endpoint(&self) -> Url {
+        self.host
+            .join(ENDPOINT)
+            .expect("parsing of static endpoint")
+    }
+
+    /// Post `input` as job of `request_type`, retrying failed requests as configured in the settings.
+    /// Requests creating expenses are only retried if Expensify surely did not handle them, to not create
+    /// duplicate expenses.
     pub fn post(
         &self,
         request_type: &str,
         input: impl Serialize,
     ) -> Result<json::Value, failure::Error> {
-        let url = self
-            .host
-            .join(ENDPOINT)
-            .expect("parsing of static endpoint");
+        let url = self.endpoint();
 
         let request_payload = json!({
             "type": request_type.to_owned(),
@@ -57,22 +177,154 @@ impl Client {
         let json_str = json::to_string(&request_payload)?;
         let params = [("requestJobDescription", &json_str)];
 
-        let mut response = reqwest::Client::new()
-            .post(url)
-            .form(&params)
-            .send()
-            .context("Post request failed")?;
-        let value: json::Value = response.json().context("failed to parse body as json")?;
-
-        if response.status().is_success() {
-            match value.get("responseCode").and_then(|v| v.as_u64()) {
-                Some(code) if code < 200 || code >= 300 => Err(into_err(code as u16, value)),
-                _ => Ok(value),
+        let client = reqwest::Client::builder()
+            .timeout(self.settings.timeout)
+            .build()
+            .context("Could not create http client")?;
+        let mut backoff = self.settings.backoff;
+        let mut retries = 0;
+        loop {
+            let (err, retry) = match self.post_once(&client, url.clone(), &params) {
+                Ok(value) => return Ok(value),
+                Err(failure) => failure,
+            };
+            let wait = match retry {
+                Retry::Safe(wait) => wait,
+                Retry::IfIdempotent if request_type != CREATE => None,
+                Retry::IfIdempotent | Retry::Never => return Err(gave_up(err, retries)),
+            };
+            let wait = match wait {
+                Some(wait) if wait > self.settings.max_backoff => {
+                    return Err(gave_up(err, retries))
+                }
+                Some(wait) => wait,
+                None => jittered(backoff),
+            };
+            if retries == self.settings.max_retries {
+                return Err(gave_up(err, retries));
             }
-        } else {
-            Err(into_err(response.status().as_u16(), value))
+            thread::sleep(wait);
+            backoff = cmp::min(backoff * 2, self.settings.max_backoff);
+            retries += 1;
         }
     }
+
+    fn post_once(
+        &self,
+        client: &reqwest::Client,
+        url: Url,
+        params: &[(&str, &String)],
+    ) -> Result<json::Value, (failure::Error, Retry)> {
+        let mut response = client.post(url).form(params).send().map_err(|err| {
+            let retry = if was_refused(&err) {
+                Retry::Safe(None)
+            } else {
+                Retry::IfIdempotent
+            };
+            (err.context("Post request failed").into(), retry)
+        })?;
+        let status = response.status();
+        let wait = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(retry_after);
+        let body = response.text().map_err(|err| {
+            (
+                err.context("Could not read response").into(),
+                Retry::IfIdempotent,
+            )
+        })?;
+
+        let err = match json::from_str::<json::Value>(&body) {
+            Ok(value) => match value.get("responseCode").and_then(|v| v.as_u64()) {
+                _ if !status.is_success() => Error::from_response(status.as_u16(), &value),
+                Some(code) if code < 200 || code >= 300 => {
+                    Error::from_response(code as u16, &value)
+                }
+                _ => return Ok(value),
+            },
+            Err(err) => {
+                if status.is_success() {
+                    return Err((
+                        err.context("failed to parse body as json").into(),
+                        Retry::Never,
+                    ));
+                }
+                Error::from_code(status.as_u16(), body)
+            }
+        };
+        let retry = match err {
+            Error::RateLimit { .. } | Error::Server { code: 503, .. } => Retry::Safe(wait),
+            Error::Server { .. } => Retry::IfIdempotent,
+            _ => Retry::Never,
+        };
+        Err((err.into(), retry))
+    }
+}
+
+fn gave_up(err: failure::Error, retries: u32) -> failure::Error {
+    if retries == 0 {
+        err
+    } else {
+        err.context(format!("Gave up after {} retries", retries))
+            .into()
+    }
+}
+
+/// Whether the request failing with `err` surely had no effect, as Expensify rejected it or it never
+/// reached Expensify. Failures leaving the outcome unknown, like timeouts, are not among them.
+pub fn had_no_effect(err: &failure::Error) -> bool {
+    err.iter_chain()
+        .any(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::Server { code, .. }) => *code == 503,
+            Some(Error::Unexpected { .. }) => false,
+            Some(_) => true,
+            None => cause
+                .downcast_ref::<reqwest::Error>()
+                .map_or(false, was_refused),
+        })
+}
+
+/// Whether the connection for the request was refused, so it was never sent.
+fn was_refused(err: &reqwest::Error) -> bool {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::ConnectionRefused {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// A random duration between half of `backoff` and `backoff`.
+fn jittered(backoff: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u64(now.as_secs());
+        hasher.write_u32(now.subsec_nanos());
+    }
+    let half = backoff / 2;
+    half + half * (hasher.finish() % 1000) as u32 / 1000
+}
+
+/// The time to wait according to a 'Retry-After' header, which is either a number of seconds
+/// or a date like 'Wed, 21 Oct 2015 07:28:00 GMT'.
+pub fn retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.with_timezone(&Utc)
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
 }
 
 #[derive(Serialize, Deserialize)]
@@ -85,6 +337,14 @@ pub struct TransactionList {
 
     #[serde(rename = "transactionList")]
     pub transaction_list: Vec<TransactionListElement>,
+
+    /// Remarks about how the list was generated. They are shown before posting, but not sent to Expensify.
+    #[serde(skip)]
+    pub notes: Vec<String>,
+
+    /// The days to add to the local record once the list was posted successfully.
+    #[serde(skip)]
+    pub records: Vec<Entry>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -115,4 +375,70 @@ pub struct TransactionListElement {
 
     #[serde(rename = "comment")]
     pub comment: String,
+
+    /// An identifier of the expense in other systems, see `external_id()`.
+    #[serde(
+        rename = "externalID",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub external_id: Option<String>,
+}
+
+/// A stable identifier of the expense of `kind` created on `date` with `amount` using the context of the given name.
+/// Posting the same logical expense again yields the same identifier, across versions and platforms.
+pub fn external_id(context: &str, date: &str, kind: &str, amount: i64) -> String {
+    // 64 bit FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in format!("{}\n{}\n{}\n{}", context, date, kind, amount).bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("expend-{:016x}", hash)
+}
+
+/// Set the external identifier of all transactions in `payload` which do not have one yet,
+/// using their merchant as kind. Transactions which are alike get the number of their occurrence
+/// added to the kind, so each one is posted.
+pub fn add_external_ids(payload: &mut json::Value, context: &str) {
+    if let Some(transactions) = payload
+        .get_mut("transactionList")
+        .and_then(json::Value::as_array_mut)
+    {
+        let mut seen: BTreeSet<String> = transactions
+            .iter()
+            .filter_map(|t| t.get(EXTERNAL_ID).and_then(json::Value::as_str))
+            .map(ToOwned::to_owned)
+            .collect();
+        for transaction in transactions {
+            if transaction.get(EXTERNAL_ID).is_some() {
+                continue;
+            }
+            let id = {
+                let field = |name| transaction.get(name).and_then(json::Value::as_str);
+                let (created, amount) = match (
+                    field("created"),
+                    transaction.get("amount").and_then(json::Value::as_i64),
+                ) {
+                    (Some(created), Some(amount)) => (created, amount),
+                    _ => continue,
+                };
+                let kind = format!("from-file:{}", field("merchant").unwrap_or_default());
+                let mut id = external_id(context, created, &kind, amount);
+                let mut occurrence = 1;
+                while seen.contains(&id) {
+                    occurrence += 1;
+                    id = external_id(
+                        context,
+                        created,
+                        &format!("{}#{}", kind, occurrence),
+                        amount,
+                    );
+                }
+                id
+            };
+            seen.insert(id.clone());
+            transaction[EXTERNAL_ID] = json!(id);
+        }
+    }
 }
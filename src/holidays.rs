@@ -0,0 +1,353 @@
+use chrono::prelude::*;
+use failure::{Error, ResultExt};
+use serde_yaml;
+use std::{collections::BTreeMap, fmt, fs::File, io::Read, path::Path, str::FromStr};
+use time::Duration;
+use {from_date_string, to_date_string};
+
+macro_rules! states {
+    ($($state:ident => $code:expr, $name:expr;)*) => {
+        /// A German federal state, each of which has its own set of public holidays.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum State {
+            $($state,)*
+        }
+
+        impl State {
+            /// The official codes of all states, as accepted by `from_str()`.
+            pub const CODES: &'static [&'static str] = &[$($code,)*];
+
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(State::$state => $code,)*
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(State::$state => $name,)*
+                }
+            }
+
+            fn all() -> &'static [State] {
+                &[$(State::$state,)*]
+            }
+        }
+    };
+}
+
+states! {
+    BadenWuerttemberg => "BW", "Baden-Württemberg";
+    Bavaria => "BY", "Bavaria";
+    Berlin => "BE", "Berlin";
+    Brandenburg => "BB", "Brandenburg";
+    Bremen => "HB", "Bremen";
+    Hamburg => "HH", "Hamburg";
+    Hesse => "HE", "Hesse";
+    MecklenburgVorpommern => "MV", "Mecklenburg-Vorpommern";
+    LowerSaxony => "NI", "Lower Saxony";
+    NorthRhineWestphalia => "NW", "North Rhine-Westphalia";
+    RhinelandPalatinate => "RP", "Rhineland-Palatinate";
+    Saarland => "SL", "Saarland";
+    Saxony => "SN", "Saxony";
+    SaxonyAnhalt => "ST", "Saxony-Anhalt";
+    SchleswigHolstein => "SH", "Schleswig-Holstein";
+    Thuringia => "TH", "Thuringia";
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for State {
+    type Err = Error;
+
+    /// Parses codes like 'BY' or 'DE-BY', as well as names like 'Bavaria'. Case-insensitive.
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        let s = s.trim();
+        let code = match (s.get(..3), s.get(3..)) {
+            (Some(prefix), Some(code)) if prefix.eq_ignore_ascii_case("de-") => code,
+            _ => s,
+        };
+        State::all()
+            .iter()
+            .find(|state| {
+                state.code().eq_ignore_ascii_case(code) || state.name().eq_ignore_ascii_case(s)
+            })
+            .cloned()
+            .ok_or_else(|| {
+                format_err!(
+                    "Unknown German federal state '{}' - valid codes are {}",
+                    s,
+                    State::CODES.join(", ")
+                )
+            })
+    }
+}
+
+impl State {
+    /// All public holidays of the given year in this state, following the current rules.
+    fn holidays(&self, year: i32) -> Vec<(NaiveDate, &'static str)> {
+        use self::State::*;
+        let date = |month, day| NaiveDate::from_ymd(year, month, day);
+        let easter = easter_sunday(year);
+        let after_easter = |days| easter + Duration::days(days);
+        let mut holidays = vec![
+            (date(1, 1), "New Year's Day"),
+            (after_easter(-2), "Good Friday"),
+            (after_easter(1), "Easter Monday"),
+            (date(5, 1), "Labour Day"),
+            (after_easter(39), "Ascension Day"),
+            (after_easter(50), "Whit Monday"),
+            (date(10, 3), "Day of German Unity"),
+            (date(12, 25), "Christmas Day"),
+            (date(12, 26), "Boxing Day"),
+        ];
+        let mut add_in = |states: &[State], holiday| {
+            if states.contains(self) {
+                holidays.push(holiday)
+            }
+        };
+        add_in(
+            &[BadenWuerttemberg, Bavaria, SaxonyAnhalt],
+            (date(1, 6), "Epiphany"),
+        );
+        if year >= 2019 {
+            add_in(&[Berlin], (date(3, 8), "International Women's Day"));
+            add_in(&[Thuringia], (date(9, 20), "World Children's Day"));
+        }
+        if year >= 2023 {
+            add_in(
+                &[MecklenburgVorpommern],
+                (date(3, 8), "International Women's Day"),
+            );
+        }
+        add_in(&[Brandenburg], (easter, "Easter Sunday"));
+        add_in(&[Brandenburg], (after_easter(49), "Whit Sunday"));
+        add_in(
+            &[
+                BadenWuerttemberg,
+                Bavaria,
+                Hesse,
+                NorthRhineWestphalia,
+                RhinelandPalatinate,
+                Saarland,
+            ],
+            (after_easter(60), "Corpus Christi"),
+        );
+        add_in(&[Saarland], (date(8, 15), "Assumption Day"));
+        if year == 2017 {
+            add_in(State::all(), (date(10, 31), "Reformation Day"));
+        } else if year >= 2018 {
+            add_in(
+                &[
+                    Brandenburg,
+                    Bremen,
+                    Hamburg,
+                    MecklenburgVorpommern,
+                    LowerSaxony,
+                    Saxony,
+                    SaxonyAnhalt,
+                    SchleswigHolstein,
+                    Thuringia,
+                ],
+                (date(10, 31), "Reformation Day"),
+            );
+        } else {
+            add_in(
+                &[
+                    Brandenburg,
+                    MecklenburgVorpommern,
+                    Saxony,
+                    SaxonyAnhalt,
+                    Thuringia,
+                ],
+                (date(10, 31), "Reformation Day"),
+            );
+        }
+        add_in(
+            &[
+                BadenWuerttemberg,
+                Bavaria,
+                NorthRhineWestphalia,
+                RhinelandPalatinate,
+                Saarland,
+            ],
+            (date(11, 1), "All Saints' Day"),
+        );
+        add_in(&[Saxony], {
+            let nov_22 = date(11, 22);
+            let days_since_wednesday = (nov_22.weekday().num_days_from_monday() + 7 - 2) % 7;
+            (
+                nov_22 - Duration::days(days_since_wednesday.into()),
+                "Day of Prayer and Repentance",
+            )
+        });
+        holidays
+    }
+}
+
+/// Computes the date of Easter Sunday in the Gregorian calendar.
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd(year, month as u32, day as u32)
+}
+
+#[derive(Deserialize)]
+struct Holiday {
+    date: String,
+    name: String,
+}
+
+/// The public holidays to consider, made up of those of German federal states and of any other days read from files.
+#[derive(Default)]
+pub struct Holidays {
+    states: Vec<State>,
+    days: BTreeMap<Date<Utc>, String>,
+}
+
+impl Holidays {
+    pub fn from_state(state: State) -> Self {
+        Holidays {
+            states: vec![state],
+            days: BTreeMap::new(),
+        }
+    }
+
+    /// Read holidays from an ICS calendar if the file name ends with '.ics', or from a YAML list of items
+    /// with a `date` like 2018-12-24 and a `name` otherwise.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .with_context(|_| format!("Could not read holidays at '{}'", path.display()))?;
+        let is_ics = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => ext.eq_ignore_ascii_case("ics"),
+            None => false,
+        };
+        Ok(Holidays {
+            states: Vec::new(),
+            days: if is_ics {
+                from_ics(&content)
+            } else {
+                from_yaml(&content)
+            }
+            .with_context(|_| format!("Could not parse holidays at '{}'", path.display()))?,
+        })
+    }
+
+    /// Return a new instance with all holidays of `self` and `other`.
+    pub fn merged(mut self, other: Holidays) -> Self {
+        self.states.extend(other.states);
+        self.days.extend(other.days);
+        self
+    }
+
+    /// Returns the name of the holiday on `date`, if there is one.
+    pub fn on(&self, date: &Date<Utc>) -> Option<String> {
+        if let Some(name) = self.days.get(date) {
+            return Some(name.clone());
+        }
+        let naive = date.naive_utc();
+        self.states.iter().find_map(|state| {
+            state
+                .holidays(naive.year())
+                .into_iter()
+                .find(|(day, _)| *day == naive)
+                .map(|(_, name)| name.to_owned())
+        })
+    }
+}
+
+fn from_yaml(content: &str) -> Result<BTreeMap<Date<Utc>, String>, Error> {
+    let holidays: Vec<Holiday> = serde_yaml::from_str(content)?;
+    holidays
+        .into_iter()
+        .map(|h| Ok((from_date_string(&h.date)?, h.name)))
+        .collect()
+}
+
+/// Read all-day events, which may span multiple days, from an ICS calendar.
+fn from_ics(content: &str) -> Result<BTreeMap<Date<Utc>, String>, Error> {
+    // Long lines are folded by inserting a line break followed by a single whitespace.
+    let unfolded = content
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+    let ics_date = |value: &str| {
+        if value.len() < 8 || !value.is_char_boundary(8) {
+            bail!("Invalid date '{}'", value);
+        }
+        from_date_string(&format!(
+            "{}-{}-{}",
+            &value[..4],
+            &value[4..6],
+            &value[6..8]
+        ))
+    };
+
+    let mut days = BTreeMap::new();
+    let (mut start, mut end, mut name) = (None, None, None);
+    for line in unfolded.lines().map(str::trim_end) {
+        let (key, value) = match line.find(':') {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => continue,
+        };
+        // Keys may have parameters, like 'DTSTART;VALUE=DATE'.
+        match key.split(';').next().unwrap_or(key) {
+            "BEGIN" if value == "VEVENT" => {
+                start = None;
+                end = None;
+                name = None;
+            }
+            "DTSTART" => start = Some(ics_date(value)?),
+            "DTEND" => end = Some(ics_date(value)?),
+            "SUMMARY" => name = Some(value.replace("\\,", ",")),
+            "END" if value == "VEVENT" => {
+                let mut day = start.take().ok_or_else(|| {
+                    format_err!(
+                        "Event '{}' has no start date",
+                        name.clone().unwrap_or_default()
+                    )
+                })?;
+                let name = name.take().unwrap_or_else(|| "Holiday".to_owned());
+                // The end date of all-day events is exclusive.
+                let end = end.take().unwrap_or_else(|| day.succ());
+                loop {
+                    days.insert(day, name.clone());
+                    day = day.succ();
+                    if day >= end {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(days)
+}
+
+/// Describes the given holidays like 'Whit Monday on 2019-06-10'.
+pub(crate) fn describe(holidays: &[(Date<Utc>, String)]) -> String {
+    holidays
+        .iter()
+        .map(|(date, name)| format!("{} on {}", name, to_date_string(date)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,118 @@
+use chrono::{Date, Datelike, Utc};
+use expensify::{TransactionList, TransactionListElement};
+use failure::Error;
+use perdiem::to_merchant;
+use record::Entry;
+use std::collections::BTreeMap;
+use Context;
+use TimePeriod;
+
+/// The kind of home-office days in the local record.
+pub const KIND: &str = "home-office";
+
+impl TransactionList {
+    /// Create one element per day of `period` at the home-office rate. Fails if this would exceed the yearly
+    /// maximum according to the local record, unless `exceed_yearly_maximum` is set.
+    pub fn from_home_office(
+        ctx: Context,
+        period: TimePeriod,
+        exceed_yearly_maximum: bool,
+    ) -> Result<Self, Error> {
+        let days: Vec<_> = period
+            .into_spans(&ctx)?
+            .iter()
+            .flat_map(|span| span.days())
+            .collect();
+        let mut transaction_list = Vec::new();
+        let mut records = Vec::new();
+        let mut rate_sets = Vec::new();
+        let mut years = BTreeMap::new();
+        for date in days {
+            let home_office = ctx.rates.home_office(&ctx.user.country, &date)?;
+            if let Some(set) = ctx
+                .rates
+                .home_office_rate_set(&ctx.user.country, &date)
+                .map(ToString::to_string)
+            {
+                if !rate_sets.contains(&set) {
+                    rate_sets.push(set);
+                }
+            }
+            let amount = home_office.rate as i32;
+            transaction_list.push(to_element(&date, &ctx, amount));
+            records.push(Entry {
+                date,
+                kind: KIND.to_owned(),
+                context: ctx.name.clone(),
+                project: ctx.user.project.clone(),
+                amount,
+                external_id: None,
+            });
+            years
+                .entry(date.year())
+                .or_insert((home_office.yearly_maximum as i32, 0))
+                .1 += amount;
+        }
+
+        let mut notes: Vec<_> = rate_sets
+            .into_iter()
+            .map(|s| format!("Used home-office rates of rate set {}.", s))
+            .collect();
+        for (year, (maximum, claimed)) in years {
+            let recorded: i32 = ctx
+                .record
+                .entries()
+                .iter()
+                .filter(|e| e.kind == KIND && e.date.year() == year)
+                .map(|e| e.amount)
+                .sum();
+            let total = recorded + claimed;
+            let currency = ctx.user.country.currency();
+            if total > maximum {
+                let message = format!(
+                    "The home-office flat rate is limited to {} per year, but {} would reach {}, as {} have been posted already according to the local record.",
+                    currency.format(maximum),
+                    year,
+                    currency.format(total),
+                    currency.format(recorded)
+                );
+                if !exceed_yearly_maximum {
+                    bail!(
+                        "{} Use --exceed-yearly-maximum to post nonetheless.",
+                        message
+                    );
+                }
+                notes.push(message);
+            } else if total == maximum {
+                notes.push(format!(
+                    "This reaches the yearly maximum of {} for {}.",
+                    currency.format(maximum),
+                    year
+                ));
+            }
+        }
+
+        Ok(TransactionList {
+            transaction_list_type: "expenses".to_owned(),
+            employee_email: ctx.user.email.clone(),
+            transaction_list,
+            notes,
+            records,
+        })
+    }
+}
+
+fn to_element(date: &Date<Utc>, ctx: &Context, amount: i32) -> TransactionListElement {
+    TransactionListElement {
+        created: ::to_date_string(date),
+        currency: format!("{}", ctx.user.country.currency()),
+        merchant: to_merchant(1, ctx, None, "Home Office Day", amount),
+        amount,
+        category: ctx.user.categories.home_office.name.clone(),
+        tag: ctx.user.project.clone(),
+        billable: false,
+        reimbursable: true,
+        comment: ctx.comment.clone().unwrap_or_default(),
+        external_id: None,
+    }
+}
@@ -0,0 +1,328 @@
+use chrono::{Date, DateTime, Utc};
+use expensify::EXTERNAL_ID;
+use failure::{Error, ResultExt};
+use record;
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json::{self, Value};
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+use {from_date_string, EXPENSIFY_DATE_FORMAT};
+
+/// The name of the file in the context directory which keeps everything posted, or about to be posted.
+pub const FILE_NAME: &str = "ledger.jsonl";
+
+/// A payload which was posted successfully, along with the response of Expensify.
+/// Before posting, the payload is added as pending entry without response, to not lose track of
+/// posts whose outcome is unknown, for example as they timed out. Once the outcome is known, the
+/// entry is appended again with the same timestamp, and takes the place of the pending one when reading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    #[serde(
+        serialize_with = "serialize_timestamp",
+        deserialize_with = "deserialize_timestamp"
+    )]
+    pub timestamp: DateTime<Utc>,
+    /// The name of the context used, if any.
+    pub context: Option<String>,
+    /// The kind of expenses, like 'per-diem' or 'from-file'.
+    pub kind: String,
+    /// The type of the job executed by Expensify, like 'create'.
+    #[serde(rename = "type")]
+    pub payload_type: String,
+    pub payload: Value,
+    pub response: Value,
+    /// The days the post added to the local record of its context.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<record::Entry>,
+    /// The number of the entry this post reverted, if it was created by 'undo'.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<usize>,
+    /// Whether the entry was added right before posting, but the post was never confirmed to succeed.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pending: bool,
+    /// Whether the post failed before Expensify handled it, so nothing was created.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub failed: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn serialize_timestamp<S: Serializer>(timestamp: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&timestamp.to_rfc3339())
+}
+
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+    DateTime::parse_from_rfc3339(&String::deserialize(d)?)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(serde::de::Error::custom)
+}
+
+impl Entry {
+    /// Whether the post is known to have succeeded.
+    pub fn succeeded(&self) -> bool {
+        !self.pending && !self.failed
+    }
+
+    /// The transactions of the payload, which is empty for payloads without a transaction list.
+    pub fn transactions(&self) -> &[Value] {
+        self.payload
+            .get("transactionList")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    /// A payload compensating this entry, which is entry `number` of the ledger, along with the
+    /// days to subtract from the local record.
+    /// All amounts are negated, comments refer to the original post, and external identifiers are removed.
+    pub fn undo(&self, number: usize) -> Result<(Value, Vec<record::Entry>), Error> {
+        if self.payload_type != "create" || self.transactions().is_empty() {
+            bail!(
+                "Post #{} of type '{}' cannot be undone as it did not create any expenses.",
+                number,
+                self.payload_type
+            );
+        }
+        let reference = format!(
+            "Undo of #{} posted on {}",
+            number,
+            self.timestamp.format(EXPENSIFY_DATE_FORMAT)
+        );
+        let mut payload = self.payload.clone();
+        if let Some(transactions) = payload
+            .get_mut("transactionList")
+            .and_then(Value::as_array_mut)
+        {
+            for transaction in transactions {
+                let amount = transaction
+                    .get("amount")
+                    .and_then(Value::as_i64)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "Post #{} contains an expense without amount, which cannot be undone.",
+                            number
+                        )
+                    })?;
+                let comment = match transaction.get("comment").and_then(Value::as_str) {
+                    Some(comment) if !comment.is_empty() => format!("{}: {}", reference, comment),
+                    _ => reference.clone(),
+                };
+                transaction["amount"] = json!(-amount);
+                transaction["comment"] = json!(comment);
+                if let Some(transaction) = transaction.as_object_mut() {
+                    transaction.remove(EXTERNAL_ID);
+                }
+            }
+        }
+        let records = self
+            .records
+            .iter()
+            .map(|entry| record::Entry {
+                amount: -entry.amount,
+                external_id: None,
+                ..entry.clone()
+            })
+            .collect();
+        Ok((payload, records))
+    }
+}
+
+/// Append `entry` to the ledger at `path`, creating the file if needed.
+pub fn append(path: &Path, entry: &Entry) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|_| format!("Could not open ledger at '{}'", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// The external identifiers of all expenses and days posted according to `entries`, except for the ones
+/// undone later. Pending posts count as posted, unless `include_pending` is false.
+pub fn external_ids(entries: &[Entry], include_pending: bool) -> BTreeSet<String> {
+    let ids_of = |entry: &Entry| -> Vec<String> {
+        entry
+            .transactions()
+            .iter()
+            .filter_map(|t| t.get(EXTERNAL_ID).and_then(Value::as_str))
+            .map(ToOwned::to_owned)
+            .chain(entry.records.iter().filter_map(|r| r.external_id.clone()))
+            .collect()
+    };
+    let mut ids = BTreeSet::new();
+    for entry in entries {
+        if entry.failed {
+            continue;
+        }
+        if entry.pending {
+            if include_pending {
+                ids.extend(ids_of(entry));
+            }
+            continue;
+        }
+        match entry
+            .undoes
+            .and_then(|number| number.checked_sub(1))
+            .and_then(|index| entries.get(index))
+        {
+            Some(undone) => {
+                for id in ids_of(undone) {
+                    ids.remove(&id);
+                }
+            }
+            None => ids.extend(ids_of(entry)),
+        }
+    }
+    ids
+}
+
+/// The error if all expenses to post were posted before.
+pub(crate) fn nothing_left() -> Error {
+    format_err!(
+        "All expenses were posted before according to the ledger - there is nothing left to post."
+    )
+}
+
+/// Remove all transactions from `payload` whose external identifier is contained in `posted`, along with
+/// their days in `records`. Returns notes about the skipped transactions.
+pub(crate) fn skip_posted(
+    payload: &mut Value,
+    records: &mut Vec<record::Entry>,
+    posted: &BTreeSet<String>,
+) -> Result<Vec<String>, Error> {
+    let is_posted = |id: Option<&str>| id.map_or(false, |id| posted.contains(id));
+    let mut notes = Vec::new();
+    if let Some(transactions) = payload
+        .get_mut("transactionList")
+        .and_then(Value::as_array_mut)
+    {
+        let count = transactions.len();
+        transactions.retain(|t| {
+            let skip = is_posted(t.get(EXTERNAL_ID).and_then(Value::as_str));
+            if skip {
+                let field = |name| t.get(name).and_then(Value::as_str).unwrap_or_default();
+                notes.push(format!(
+                    "Skipped '{}' created {} as it was posted before.",
+                    field("merchant"),
+                    field("created")
+                ));
+            }
+            !skip
+        });
+        if count > 0 && transactions.is_empty() {
+            return Err(nothing_left());
+        }
+    }
+    records.retain(|r| !is_posted(r.external_id.as_ref().map(String::as_str)));
+    Ok(notes)
+}
+
+/// Read all entries of the ledger at `path`, oldest first. It is empty if the file does not exist yet.
+/// Entries recording the outcome of a pending entry take its place.
+pub fn read(path: &Path) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+    if !path.is_file() {
+        return Ok(entries);
+    }
+    let file = File::open(path)
+        .with_context(|_| format!("Could not open ledger at '{}'", path.display()))?;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line).with_context(|_| {
+            format!(
+                "Could not parse line {} of the ledger at '{}'",
+                number + 1,
+                path.display()
+            )
+        })?;
+        match entries
+            .iter()
+            .rposition(|e: &Entry| e.pending && e.timestamp == entry.timestamp)
+        {
+            Some(index) => entries[index] = entry,
+            None => entries.push(entry),
+        }
+    }
+    Ok(entries)
+}
+
+/// Selects entries of the ledger and their transactions. Unset fields match everything.
+#[derive(Default)]
+pub struct Filter {
+    /// The first date of transactions, inclusive.
+    pub from: Option<Date<Utc>>,
+    /// The last date of transactions, inclusive.
+    pub to: Option<Date<Utc>>,
+    pub context: Option<String>,
+    pub kind: Option<String>,
+    /// The smallest amount of transactions, in cents.
+    pub min_amount: Option<i32>,
+    /// The largest amount of transactions, in cents.
+    pub max_amount: Option<i32>,
+}
+
+impl Filter {
+    /// The transactions of `entry` matching the filter, or `None` if the entry does not match at all.
+    /// Entries without transactions only match if no transaction is filtered by date or amount.
+    pub fn transactions<'a>(&self, entry: &'a Entry) -> Option<Vec<&'a Value>> {
+        if let Some(ref context) = self.context {
+            if entry.context.as_ref() != Some(context) {
+                return None;
+            }
+        }
+        if let Some(ref kind) = self.kind {
+            if !entry.kind.eq_ignore_ascii_case(kind) {
+                return None;
+            }
+        }
+        let transactions = entry.transactions();
+        if transactions.is_empty() {
+            let filters_transactions = self.from.is_some()
+                || self.to.is_some()
+                || self.min_amount.is_some()
+                || self.max_amount.is_some();
+            return if filters_transactions {
+                None
+            } else {
+                Some(Vec::new())
+            };
+        }
+        let matching: Vec<_> = transactions.iter().filter(|t| self.matches(t)).collect();
+        if matching.is_empty() {
+            None
+        } else {
+            Some(matching)
+        }
+    }
+
+    fn matches(&self, transaction: &Value) -> bool {
+        let date = transaction
+            .get("created")
+            .and_then(Value::as_str)
+            .and_then(|d| from_date_string(d).ok());
+        let date_matches = match date {
+            Some(date) => {
+                self.from.iter().all(|from| *from <= date) && self.to.iter().all(|to| date <= *to)
+            }
+            None => self.from.is_none() && self.to.is_none(),
+        };
+        let amount_matches = match transaction.get("amount").and_then(Value::as_i64) {
+            Some(amount) => {
+                self.min_amount.iter().all(|min| i64::from(*min) <= amount)
+                    && self.max_amount.iter().all(|max| amount <= i64::from(*max))
+            }
+            None => self.min_amount.is_none() && self.max_amount.is_none(),
+        };
+        date_matches && amount_matches
+    }
+}
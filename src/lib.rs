@@ -2,6 +2,7 @@
 extern crate failure;
 extern crate reqwest;
 extern crate serde;
+extern crate serde_yaml;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
@@ -11,11 +12,23 @@ extern crate time;
 
 use chrono::prelude::*;
 use failure::{Error, ResultExt};
+use std::collections::BTreeSet;
 
+mod accommodation;
+mod conflicts;
 pub mod context;
 pub mod expensify;
+pub mod holidays;
+mod home_office;
+pub mod ledger;
+pub mod mileage;
 pub mod perdiem;
+pub mod rates;
+pub mod record;
+mod reference_date;
+mod three_month_rule;
 mod timeperiod;
+pub mod trip;
 mod weekday;
 
 use expensify::TransactionList;
@@ -23,36 +36,209 @@ use expensify::TransactionList;
 const EXPENSIFY_DATE_FORMAT: &str = "%Y-%m-%d";
 
 pub use context::{Categories, Category, Context, Tag, Tags, UserContext};
-pub use timeperiod::TimePeriod;
+pub use rates::RateTable;
+pub use reference_date::parse_reference_date;
+pub use timeperiod::{DaySelection, DaySet, TimePeriod};
 pub use weekday::Weekday;
 
 pub enum Command {
     Payload(Option<Context>, String, serde_json::Value),
-    PerDiem(Context, TimePeriod, perdiem::Kind, perdiem::Mode),
+    PerDiem(
+        Context,
+        TimePeriod,
+        perdiem::Kind,
+        perdiem::Mode,
+        perdiem::Meals,
+        /// Whether to ignore the three-month rule.
+        bool,
+    ),
+    Mileage(Context, TimePeriod, u32),
+    Accommodation(Context, TimePeriod),
+    /// The time period, and whether the yearly maximum may be exceeded.
+    HomeOffice(Context, TimePeriod, bool),
+    /// The trip, the meals provided and whether to ignore the three-month rule.
+    Trip(Context, trip::Trip, perdiem::Meals, bool),
+    /// The context of the original post, if any, and the number of its entry in the ledger.
+    Undo(Option<Context>, usize, ledger::Entry),
 }
 
+impl Command {
+    /// The names of all kinds of commands, as returned by `kind()`.
+    pub const KINDS: &'static [&'static str] = &[
+        "from-file",
+        "per-diem",
+        "mileage",
+        "accommodation",
+        "home-office",
+        "trip",
+        "undo",
+    ];
+
+    /// The name of the kind of expenses the command creates, like 'per-diem'.
+    pub fn kind(&self) -> &'static str {
+        use self::Command::*;
+        match self {
+            Payload(..) => "from-file",
+            PerDiem(..) => "per-diem",
+            Mileage(..) => "mileage",
+            Accommodation(..) => "accommodation",
+            HomeOffice(..) => "home-office",
+            Trip(..) => "trip",
+            Undo(..) => "undo",
+        }
+    }
+
+    /// The context the command uses, if any.
+    pub fn context(&self) -> Option<&Context> {
+        use self::Command::*;
+        match self {
+            Payload(ctx, _, _) | Undo(ctx, _, _) => ctx.as_ref(),
+            PerDiem(ctx, ..)
+            | Mileage(ctx, ..)
+            | Accommodation(ctx, ..)
+            | HomeOffice(ctx, ..)
+            | Trip(ctx, ..) => Some(ctx),
+        }
+    }
+
+    fn context_mut(&mut self) -> Option<&mut Context> {
+        use self::Command::*;
+        match self {
+            Payload(ctx, _, _) | Undo(ctx, _, _) => ctx.as_mut(),
+            PerDiem(ctx, ..)
+            | Mileage(ctx, ..)
+            | Accommodation(ctx, ..)
+            | HomeOffice(ctx, ..)
+            | Trip(ctx, ..) => Some(ctx),
+        }
+    }
+}
+
+/// A payload which was posted successfully.
+pub struct Posted {
+    /// The type of the job executed by Expensify, like 'create'.
+    pub payload_type: String,
+    pub payload: serde_json::Value,
+    /// The days added to the local record of the context.
+    pub records: Vec<record::Entry>,
+    pub response: serde_json::Value,
+}
+
+/// Create the payload for `cmd` and post it with `client` once `pre_execute` agrees, leaving out expenses
+/// and days whose external identifiers are `posted` already.
+/// `pre_execute` receives the payload type, the payload, notes about it and the days to record.
 pub fn execute(
-    user_id: String,
-    password: String,
-    cmd: Command,
-    pre_execute: impl FnOnce(&str, &serde_json::Value) -> Result<(), Error>,
-) -> Result<serde_json::Value, Error> {
+    client: &expensify::Client,
+    mut cmd: Command,
+    posted: &BTreeSet<String>,
+    pre_execute: impl FnOnce(&str, &serde_json::Value, &[String], &[record::Entry]) -> Result<(), Error>,
+) -> Result<Posted, Error> {
     use self::Command::*;
 
-    let client = expensify::Client::new(None, user_id, password);
-    let (payload_type, payload) = match cmd {
-        Payload(None, pt, p) => (pt, p),
-        Payload(Some(ctx), pt, mut p) => (pt, ctx.user.apply_to_value(p)),
-        PerDiem(ctx, period, kind, mode) => {
-            let payload = serde_json::value::to_value(TransactionList::from_per_diem(
-                ctx, period, kind, mode,
-            )?)?;
-            ("create".to_string(), payload)
+    if let Some(ctx) = cmd.context_mut() {
+        ctx.posted = posted.clone();
+    }
+
+    let record_file = cmd
+        .context()
+        .and_then(|ctx| ctx.record.file())
+        .map(|file| file.to_owned());
+    let list = match cmd {
+        Payload(ctx, pt, p) => {
+            let mut p = match ctx {
+                Some(ref ctx) => ctx.user.apply_to_value(p),
+                None => p,
+            };
+            expensify::add_external_ids(&mut p, ctx.as_ref().map_or("", |ctx| &ctx.name));
+            let notes = ledger::skip_posted(&mut p, &mut Vec::new(), posted)?;
+            pre_execute(&pt, &p, &notes, &[])?;
+            return post(client, pt, p, Vec::new(), None);
+        }
+        Undo(_, number, entry) => {
+            let (p, records) = entry.undo(number)?;
+            let mut notes = vec![format!(
+                "This reverts post #{} of kind '{}', posted on {}.",
+                number,
+                entry.kind,
+                entry.timestamp.format("%Y-%m-%d %H:%M UTC")
+            )];
+            if !records.is_empty() && record_file.is_none() {
+                notes.push(format!(
+                    "The days of post #{} will remain in the local record as its context is unknown.",
+                    number
+                ));
+            }
+            pre_execute(&entry.payload_type, &p, &notes, &records)?;
+            return post(client, entry.payload_type, p, records, record_file);
+        }
+        PerDiem(ctx, period, kind, mode, meals, ignore_three_month_rule) => {
+            TransactionList::from_per_diem(ctx, period, kind, mode, meals, ignore_three_month_rule)?
+        }
+        Trip(ctx, trip, meals, ignore_three_month_rule) => {
+            TransactionList::from_trip(ctx, trip, meals, ignore_three_month_rule)?
+        }
+        Mileage(ctx, period, distance) => TransactionList::from_mileage(ctx, period, distance)?,
+        Accommodation(ctx, period) => TransactionList::from_accommodation(ctx, period)?,
+        HomeOffice(ctx, period, exceed_yearly_maximum) => {
+            TransactionList::from_home_office(ctx, period, exceed_yearly_maximum)?
         }
     };
-    let payload = serde_json::value::to_value(payload)?;
-    pre_execute(&payload_type, &payload)?;
-    client.post(&payload_type, payload)
+    let payload_type = "create";
+    let mut payload = serde_json::value::to_value(&list)?;
+    let mut records = list.records;
+    let mut notes = list.notes;
+    notes.extend(ledger::skip_posted(&mut payload, &mut records, posted)?);
+    pre_execute(payload_type, &payload, &notes, &records)?;
+    post(
+        client,
+        payload_type.to_owned(),
+        payload,
+        records,
+        record_file,
+    )
+}
+
+fn post(
+    client: &expensify::Client,
+    payload_type: String,
+    payload: serde_json::Value,
+    records: Vec<record::Entry>,
+    record_file: Option<std::path::PathBuf>,
+) -> Result<Posted, Error> {
+    let response = client.post(&payload_type, payload.clone())?;
+    let records = match record_file {
+        Some(ref file) if !records.is_empty() => {
+            record::append(file, &records).with_context(|_| {
+                format!(
+                    "The expenses were posted, but could not be added to the record at '{}'",
+                    file.display()
+                )
+            })?;
+            records
+        }
+        _ => Vec::new(),
+    };
+    Ok(Posted {
+        payload_type,
+        payload,
+        records,
+        response,
+    })
+}
+
+fn to_date_string(d: &Date<Utc>) -> String {
+    d.format(EXPENSIFY_DATE_FORMAT).to_string()
+}
+
+pub fn from_date_time_string(s: &str) -> Result<DateTime<Utc>, Error> {
+    let date_time = NaiveDateTime::parse_from_str(&s.replacen(' ', "T", 1), "%Y-%m-%dT%H:%M")
+        .with_context(|_| {
+            format!(
+                "Could not parse date and time '{}', expected something like '2018-09-25T07:30'",
+                s
+            )
+        })?;
+    Ok(DateTime::from_utc(date_time, Utc))
 }
 
 pub fn from_date_string(s: &str) -> Result<Date<Utc>, Error> {
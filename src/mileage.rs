@@ -0,0 +1,85 @@
+use chrono::{Date, Utc};
+use expensify::{TransactionList, TransactionListElement};
+use failure::Error;
+use rates::split_by_rate_set;
+use std::convert::TryFrom;
+use timeperiod::Span;
+use Context;
+use TimePeriod;
+
+impl TransactionList {
+    pub fn from_mileage(ctx: Context, period: TimePeriod, distance: u32) -> Result<Self, Error> {
+        let (transaction_list, rate_sets) = period.into_mileage_transactions(&ctx, distance)?;
+        Ok(TransactionList {
+            transaction_list_type: "expenses".to_owned(),
+            employee_email: ctx.user.email.clone(),
+            transaction_list,
+            notes: rate_sets
+                .into_iter()
+                .map(|s| format!("Used mileage rates of rate set {}.", s))
+                .collect(),
+            records: Vec::new(),
+        })
+    }
+}
+
+fn to_merchant(distance: u32, ctx: &Context, date: &Date<Utc>) -> Result<String, Error> {
+    let mileage = ctx.rates.mileage(&ctx.user.country, date)?;
+    Ok(format!(
+        "{} {} @ {} / {}",
+        distance,
+        mileage.unit,
+        ctx.user.country.currency().format(mileage.rate as i32),
+        mileage.unit
+    ))
+}
+
+fn to_element(span: &Span, ctx: &Context, distance: u32) -> Result<TransactionListElement, Error> {
+    let too_far = || {
+        format_err!(
+            "A distance of {} per day on {} days is too far to file",
+            distance,
+            span.num_days()
+        )
+    };
+    let distance = distance.checked_mul(span.num_days()).ok_or_else(too_far)?;
+    let date = span.first_day();
+    let amount = ctx
+        .rates
+        .mileage(&ctx.user.country, date)?
+        .rate
+        .checked_mul(distance)
+        .and_then(|amount| i32::try_from(amount).ok())
+        .ok_or_else(too_far)?;
+    Ok(TransactionListElement {
+        created: span.created(),
+        currency: format!("{}", ctx.user.country.currency()),
+        merchant: to_merchant(distance, ctx, date)?,
+        amount,
+        category: ctx.user.categories.mileage.name.clone(),
+        tag: format!("{}:{}", ctx.user.project.clone(), ctx.user.tags.travel.name),
+        billable: ctx.user.tags.travel.billable,
+        reimbursable: true,
+        comment: span.comment(ctx),
+        external_id: None,
+    })
+}
+
+impl TimePeriod {
+    fn into_mileage_transactions(
+        self,
+        ctx: &Context,
+        distance: u32,
+    ) -> Result<(Vec<TransactionListElement>, Vec<String>), Error> {
+        let (spans, rate_sets) = split_by_rate_set(
+            self.into_spans(ctx)?,
+            |date| ctx.rates.mileage_rate_set(&ctx.user.country, date),
+            |_| None,
+        );
+        let elements = spans
+            .iter()
+            .map(|span| to_element(span, ctx, distance))
+            .collect::<Result<_, _>>()?;
+        Ok((elements, rate_sets))
+    }
+}
@@ -1,27 +1,176 @@
 use chrono::{Date, Utc};
-use context::{Country, Country::*, Destination};
-use expensify::{TransactionList, TransactionListElement};
+use conflicts;
+use context::Destination;
+use expensify::{external_id, TransactionList, TransactionListElement};
 use failure::Error;
+use ledger;
+use rates::split_by_rate_set;
+use record::Entry;
 use std::fmt;
 use std::str::FromStr;
+use three_month_rule;
+use timeperiod::Span;
+use to_date_string;
+use trip::Trip;
+use Context;
 use TimePeriod;
-use {Context, EXPENSIFY_DATE_FORMAT};
 
 impl TransactionList {
+    /// Fails if a day is outside of the three-month window at the same workplace, unless
+    /// `ignore_three_month_rule` is set.
     pub fn from_per_diem(
         ctx: Context,
         period: TimePeriod,
         kind: Kind,
         mode: Mode,
+        meals: Meals,
+        ignore_three_month_rule: bool,
     ) -> Result<Self, Error> {
+        let per_diems = period.into_transactions(&ctx, kind, mode, meals)?;
+        Self::from_per_diems(&ctx, per_diems, ignore_three_month_rule)
+    }
+
+    /// Breakfast is not deducted on the first day of trips with an overnight stay, unless overridden for that day.
+    pub fn from_trip(
+        ctx: Context,
+        trip: Trip,
+        meals: Meals,
+        ignore_three_month_rule: bool,
+    ) -> Result<Self, Error> {
+        let mut per_diems = PerDiems::default();
+        let days = trip.into_days()?;
+        let num_days = days.len();
+        for (index, (span, kind)) in days.into_iter().enumerate() {
+            // There is no breakfast on the way to the first accommodation.
+            let meals = if index == 0 && num_days > 1 {
+                Meals {
+                    breakfast: false,
+                    ..meals
+                }
+            } else {
+                meals
+            };
+            into_elements(vec![span], &ctx, &kind, &Mode::Add, &meals, &mut per_diems)?;
+        }
+        Self::from_per_diems(&ctx, per_diems, ignore_three_month_rule)
+    }
+
+    fn from_per_diems(
+        ctx: &Context,
+        per_diems: PerDiems,
+        ignore_three_month_rule: bool,
+    ) -> Result<Self, Error> {
+        let mut notes: Vec<_> = per_diems
+            .rate_sets
+            .into_iter()
+            .map(|s| format!("Used per-diem rates of rate set {}.", s))
+            .collect();
+        notes.extend(three_month_rule::check(
+            ctx,
+            &per_diems.records,
+            ignore_three_month_rule,
+        )?);
+        notes.extend(conflicts::check(ctx, &per_diems.records));
+        if per_diems.elements.is_empty() && !per_diems.skipped.is_empty() {
+            return Err(ledger::nothing_left());
+        }
+        notes.extend(per_diems.skipped);
         Ok(TransactionList {
             transaction_list_type: "expenses".to_owned(),
             employee_email: ctx.user.email.clone(),
-            transaction_list: period.into_transactions(&ctx, kind, mode)?,
+            transaction_list: per_diems.elements,
+            notes,
+            records: per_diems.records,
         })
     }
 }
 
+/// The prefix of the kinds of per-diems in the local record, which is followed by the name of the `Kind`
+/// or 'meals' for meal deductions.
+pub const KIND_PREFIX: &str = "per-diem:";
+
+/// Elements of per-diems along with the days to record, the rate sets used in order of first use,
+/// and notes about days left out as they were posted before.
+#[derive(Default)]
+struct PerDiems {
+    elements: Vec<TransactionListElement>,
+    records: Vec<Entry>,
+    rate_sets: Vec<String>,
+    skipped: Vec<String>,
+}
+
+impl PerDiems {
+    /// Add `amount` for each day of `span`, leaving out days posted before.
+    /// Each day has its own external identifier, which doesn't depend on how days were grouped into spans.
+    fn push(
+        &mut self,
+        span: &Span,
+        ctx: &Context,
+        what: impl fmt::Display,
+        kind: &str,
+        amount: i32,
+    ) {
+        let kind = format!("{}{}", KIND_PREFIX, kind);
+        let day_id = |date: &Date<Utc>| {
+            external_id(&ctx.name, &to_date_string(date), &kind, i64::from(amount))
+        };
+        let (posted, days): (Vec<_>, Vec<_>) = span
+            .days()
+            .into_iter()
+            .partition(|date| ctx.posted.contains(&day_id(date)));
+        if !posted.is_empty() {
+            self.skipped.push(format!(
+                "Skipped '{}' on {} as it was posted before.",
+                what,
+                posted
+                    .iter()
+                    .map(to_date_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if days.is_empty() {
+            return;
+        }
+        let spans = if posted.is_empty() {
+            vec![span.clone()]
+        } else {
+            span.clone()
+                .split_by(|date| ctx.posted.contains(&day_id(date)))
+                .into_iter()
+                .filter(|span| !ctx.posted.contains(&day_id(span.first_day())))
+                .collect()
+        };
+        for span in spans {
+            let mut element = to_element(&span, ctx, &what, amount);
+            let days = span.days();
+            let dates = match (days.first(), days.last()) {
+                (Some(first), Some(last)) if first != last => {
+                    format!("{}..{}", to_date_string(first), to_date_string(last))
+                }
+                _ => span.created(),
+            };
+            element.external_id = Some(external_id(
+                &ctx.name,
+                &dates,
+                &kind,
+                i64::from(element.amount),
+            ));
+            for date in days {
+                self.records.push(Entry {
+                    date,
+                    kind: kind.clone(),
+                    context: ctx.name.clone(),
+                    project: ctx.user.project.clone(),
+                    amount,
+                    external_id: Some(day_id(&date)),
+                });
+            }
+            self.elements.push(element);
+        }
+    }
+}
+
 pub enum Mode {
     Add,
     Subtract,
@@ -38,6 +187,67 @@ impl<'a> ::std::ops::Mul<&'a Mode> for i32 {
     }
 }
 
+/// The meals provided on each day, for example by a hotel or the host.
+/// They are deducted as a percentage of the full-day rate, as per the German rules.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Meals {
+    pub breakfast: bool,
+    pub lunch: bool,
+    pub dinner: bool,
+}
+
+impl Meals {
+    pub fn is_empty(&self) -> bool {
+        !(self.breakfast || self.lunch || self.dinner)
+    }
+
+    fn percentage(&self) -> u32 {
+        let of = |provided: bool, percentage: u32| if provided { percentage } else { 0 };
+        of(self.breakfast, 20) + of(self.lunch, 40) + of(self.dinner, 40)
+    }
+
+    /// The deduction for a single day, which never exceeds the per-diem of that day.
+    fn amount(&self, ctx: &Context, kind: &Kind, date: &Date<Utc>) -> Result<u32, Error> {
+        let deduction = Kind::FullDay.amount(ctx, date)? * self.percentage() / 100;
+        Ok(deduction.min(kind.amount(ctx, date)?))
+    }
+}
+
+impl fmt::Display for Meals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let names: Vec<_> = [
+            (self.breakfast, "Breakfast"),
+            (self.lunch, "Lunch"),
+            (self.dinner, "Dinner"),
+        ]
+        .iter()
+        .filter(|(provided, _)| *provided)
+        .map(|(_, name)| *name)
+        .collect();
+        f.write_str(&names.join(", "))
+    }
+}
+
+impl FromStr for Meals {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        let mut meals = Meals::default();
+        for meal in s.split(',').map(str::trim).filter(|s| s.len() != 0) {
+            match meal.to_ascii_lowercase().as_str() {
+                "breakfast" => meals.breakfast = true,
+                "lunch" => meals.lunch = true,
+                "dinner" => meals.dinner = true,
+                _ => bail!(
+                    "Invalid meal '{}' - valid meals are breakfast, lunch and dinner",
+                    meal
+                ),
+            }
+        }
+        Ok(meals)
+    }
+}
+
 pub enum Kind {
     FullDay,
     Breakfast,
@@ -49,33 +259,34 @@ pub enum Kind {
 }
 
 impl Kind {
-    fn amount(&self, c: &Country, d: Option<&Destination>) -> u32 {
+    /// The identifiers of all kinds, as accepted by `from_str()`.
+    pub const NAMES: &'static [&'static str] = &[
+        "breakfast",
+        "fullday",
+        "arrival",
+        "departure",
+        "daytrip",
+        "lunch",
+        "dinner",
+    ];
+
+    pub fn name(&self) -> &'static str {
         use self::Kind::*;
-        let res = (|| {
-            Ok((match (c, d) {
-                (Germany, Some(Destination::IndiaOther)) => match self {
-                    FullDay => 320,
-                    Daytrip | Arrival | Departure => 210,
-                    anything_else => return Err(anything_else),
-                },
-                (Germany, None) => match self {
-                    FullDay => 240,
-                    Daytrip | Arrival | Departure => 120,
-                    Breakfast => 48,
-                    Lunch | Dinner => 96,
-                },
-            }) * 10)
-        })();
-        match res {
-            Ok(val) => val,
-            // It's not good for a lib to panic, but I find it too cumbersome to pass-through the error through all callers right now.
-            // Maybe eventually we will learn how to produce a valid value in all cases.
-            Err(invalid_kind) => panic!(
-                "The per-diem of kind '{}' cannot yield an amount - please try another one.",
-                invalid_kind
-            ),
+        match self {
+            FullDay => "fullday",
+            Breakfast => "breakfast",
+            Arrival => "arrival",
+            Departure => "departure",
+            Daytrip => "daytrip",
+            Lunch => "lunch",
+            Dinner => "dinner",
         }
     }
+
+    fn amount(&self, ctx: &Context, date: &Date<Utc>) -> Result<u32, Error> {
+        ctx.rates
+            .amount(&ctx.user.country, ctx.destination_on(date), self, date)
+    }
 }
 
 impl fmt::Display for Kind {
@@ -109,124 +320,140 @@ impl FromStr for Kind {
     }
 }
 
-fn to_date_string(d: &Date<Utc>) -> String {
-    d.format(EXPENSIFY_DATE_FORMAT).to_string()
-}
-
-fn to_merchant(num_days: u32, ctx: &Context, kind: &Kind, mode: &Mode) -> String {
+pub(crate) fn to_merchant(
+    num_days: u32,
+    ctx: &Context,
+    destination: Option<&Destination>,
+    what: impl fmt::Display,
+    amount: i32,
+) -> String {
     format!(
-        "{} * {}{} {} @ {}{:.2}",
+        "{} * {}{} {} @ {}",
         num_days,
-        match ctx.user.destination {
-            Some(ref destination) => format!("{} for ", destination),
+        match destination {
+            Some(destination) => format!("{} for ", destination),
             None => "".to_owned(),
         },
         ctx.user.country,
-        kind,
-        ctx.user.country.currency().symbol(),
-        ((kind.amount(&ctx.user.country, ctx.user.destination.as_ref()) / 10) as i32 * mode) as f32
-            / 10.0
+        what,
+        ctx.user.country.currency().format(amount)
     )
 }
 
-fn to_element_from_range(
-    from: &Date<Utc>,
-    to: &Date<Utc>,
-    ctx: &Context,
-    kind: &Kind,
-    mode: &Mode,
-) -> TransactionListElement {
-    let num_days = (*to - *from).num_days() + 1;
-    assert!(num_days > 0, "to-date must be larger than from-date");
-    let comment = to_comment_from_range(&from, &to);
-
-    to_element(
-        to_date_string(&from),
-        ctx.comment
-            .as_ref()
-            .map(|custom| format!("{}: {}", comment, custom))
-            .unwrap_or(comment),
-        num_days as u32,
-        ctx,
-        kind,
-        mode,
-    )
-}
 fn to_element(
-    created: String,
-    comment: String,
-    num_days: u32,
+    span: &Span,
     ctx: &Context,
-    kind: &Kind,
-    mode: &Mode,
+    what: impl fmt::Display,
+    amount: i32,
 ) -> TransactionListElement {
+    let num_days = span.num_days();
     TransactionListElement {
-        created,
+        created: span.created(),
         currency: format!("{}", ctx.user.country.currency()),
-        merchant: to_merchant(num_days, ctx, &kind, mode),
-        amount: (kind.amount(&ctx.user.country, ctx.user.destination.as_ref()) * num_days) as i32
-            * mode,
+        merchant: to_merchant(
+            num_days,
+            ctx,
+            ctx.destination_on(span.first_day()),
+            what,
+            amount,
+        ),
+        amount: amount * num_days as i32,
         category: ctx.user.categories.per_diems.name.clone(),
         tag: format!("{}:{}", ctx.user.project.clone(), ctx.user.tags.travel.name),
         billable: ctx.user.tags.travel.billable,
         reimbursable: true,
-        comment,
+        comment: span.comment(ctx),
+        external_id: None,
     }
 }
 
-fn to_comment_from_range(from: &Date<Utc>, to: &Date<Utc>) -> String {
-    format!("{} to {}", to_date_string(&from), to_date_string(&to))
-}
-
-fn to_element_single_day(
-    day: &Date<Utc>,
-    ctx: &Context,
-    kind: &Kind,
-    mode: &Mode,
-) -> TransactionListElement {
-    to_element(
-        to_date_string(day),
-        ctx.comment.clone().unwrap_or_default(),
-        1,
-        ctx,
-        kind,
-        mode,
-    )
-}
-
 impl TimePeriod {
     fn into_transactions(
         self,
         ctx: &Context,
         kind: Kind,
         mode: Mode,
-    ) -> Result<Vec<TransactionListElement>, Error> {
-        use TimePeriod::*;
-        use Weekday::Friday;
+        meals: Meals,
+    ) -> Result<PerDiems, Error> {
+        check_deductible(&meals, &kind, &mode)?;
+        let mut per_diems = PerDiems::default();
+        into_elements(
+            self.into_spans(ctx)?,
+            ctx,
+            &kind,
+            &mode,
+            &meals,
+            &mut per_diems,
+        )?;
+        Ok(per_diems)
+    }
+}
 
-        let mut ts = Vec::new();
-        let monday = ctx.monday_of_reference_date()?;
-        match self {
-            Weekdays => {
-                let friday = Friday.to_date_from(&monday)?;
-                ts.push(to_element_from_range(&monday, &friday, ctx, &kind, &mode));
-            }
-            SingleDay(day) => {
-                let day = day.to_date_from(&monday)?;
-                ts.push(to_element_single_day(&day, ctx, &kind, &mode));
-            }
-            DayRange { from, to } => {
-                let from = from.to_date_from(&monday)?;
-                let to = to.to_date_from(&monday)?;
-                ts.push(to_element_from_range(&from, &to, ctx, &kind, &mode));
+/// Fail if `meals` cannot be deducted from per-diems of `kind` posted in `mode`.
+fn check_deductible(meals: &Meals, kind: &Kind, mode: &Mode) -> Result<(), Error> {
+    if !meals.is_empty() {
+        match (kind, mode) {
+            (Kind::Breakfast, _) | (Kind::Lunch, _) | (Kind::Dinner, _) => bail!(
+                "Provided meals can only be deducted from daily per-diems, not from '{}'",
+                kind.name()
+            ),
+            (_, Mode::Subtract) => {
+                bail!("Provided meals cannot be deducted when subtracting per-diems")
             }
-            Days(d) => {
-                for day in d {
-                    let day = day.to_date_from(&monday)?;
-                    ts.push(to_element_single_day(&day, ctx, &kind, &mode));
-                }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn into_elements(
+    spans: Vec<Span>,
+    ctx: &Context,
+    kind: &Kind,
+    mode: &Mode,
+    meals: &Meals,
+    per_diems: &mut PerDiems,
+) -> Result<(), Error> {
+    let spans = spans
+        .into_iter()
+        .flat_map(|span| span.split_by(|day| ctx.destination_on(day)))
+        .collect();
+    let (spans, rate_sets) = split_by_rate_set(
+        spans,
+        |date| {
+            ctx.rates
+                .rate_set(&ctx.user.country, ctx.destination_on(date), date)
+        },
+        |date| Some(ctx.place_on(date)),
+    );
+    for set in rate_sets {
+        if !per_diems.rate_sets.contains(&set) {
+            per_diems.rate_sets.push(set);
+        }
+    }
+    for span in &spans {
+        let date = span.first_day();
+        per_diems.push(
+            span,
+            ctx,
+            kind,
+            kind.name(),
+            kind.amount(ctx, date)? as i32 * mode,
+        );
+        for span in span.clone().split_by(|day| ctx.meals_on(day, *meals)) {
+            let date = span.first_day();
+            let meals = ctx.meals_on(date, *meals);
+            check_deductible(&meals, kind, mode)?;
+            if !meals.is_empty() {
+                per_diems.push(
+                    &span,
+                    ctx,
+                    format!("Meal Deduction ({})", meals),
+                    "meals",
+                    meals.amount(ctx, kind, date)? as i32 * &Mode::Subtract,
+                );
             }
         }
-        Ok(ts)
     }
+    Ok(())
 }
@@ -0,0 +1,400 @@
+use chrono::{Date, Utc};
+use context::{normalized, Country, Destination};
+use failure::{Error, ResultExt};
+use perdiem::Kind;
+use serde::{Deserialize, Deserializer};
+use serde_yaml;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::File,
+    path::Path,
+};
+use timeperiod::Span;
+use {from_date_string, to_date_string};
+
+/// The name of the file in the context directory which overrides the built-in rates.
+pub const OVERRIDE_FILE_NAME: &str = "rates.yml";
+
+const DEFAULT_RATES: &str = include_str!("rates.yml");
+const VERSION: u32 = 2;
+
+#[derive(Deserialize)]
+pub struct RateTable {
+    version: u32,
+    rate_sets: Vec<RateSet>,
+}
+
+/// Rates which apply to transactions dated within the given, inclusive, bounds.
+/// If multiple sets apply to a date, the one with the latest `valid_from` date is used.
+#[derive(Deserialize)]
+pub struct RateSet {
+    pub name: String,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    valid_from: Option<Date<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    valid_until: Option<Date<Utc>>,
+    countries: BTreeMap<String, CountryRates>,
+}
+
+fn deserialize_date<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Date<Utc>>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(s) => from_date_string(&s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+struct CountryRates {
+    mileage: Option<Mileage>,
+    home_office: Option<HomeOffice>,
+    domestic: Option<Rates>,
+    #[serde(default)]
+    destinations: BTreeMap<String, Rates>,
+}
+
+/// The amount in cents paid per driven distance `unit`, like 'km' or 'mi'.
+#[derive(Deserialize)]
+pub struct Mileage {
+    pub rate: u32,
+    pub unit: String,
+}
+
+/// The amount in cents paid per day worked from home, up to a `yearly_maximum` in cents.
+#[derive(Deserialize)]
+pub struct HomeOffice {
+    pub rate: u32,
+    pub yearly_maximum: u32,
+}
+
+/// All amounts are in cents of the home country's currency.
+#[derive(Deserialize)]
+pub struct Rates {
+    pub fullday: Option<u32>,
+    /// Used for arrival, departure and day trips
+    pub partial: Option<u32>,
+    /// The flat rate for a night without a booked hotel
+    pub accommodation: Option<u32>,
+    pub breakfast: Option<u32>,
+    pub lunch: Option<u32>,
+    pub dinner: Option<u32>,
+}
+
+impl Rates {
+    /// The identifiers of all kinds for which we have a rate.
+    pub fn kinds(&self) -> Vec<&'static str> {
+        Kind::NAMES
+            .iter()
+            .filter(|name| name.parse().ok().and_then(|kind| self.get(&kind)).is_some())
+            .cloned()
+            .collect()
+    }
+
+    fn get(&self, kind: &Kind) -> Option<u32> {
+        use perdiem::Kind::*;
+        match kind {
+            FullDay => self.fullday,
+            Arrival | Departure | Daytrip => self.partial,
+            Breakfast => self.breakfast,
+            Lunch => self.lunch,
+            Dinner => self.dinner,
+        }
+    }
+}
+
+impl RateSet {
+    fn is_valid_at(&self, date: &Date<Utc>) -> bool {
+        self.valid_from.iter().all(|from| from <= date)
+            && self.valid_until.iter().all(|until| date <= until)
+    }
+
+    fn rates(&self, country: &Country, destination: Option<&Destination>) -> Option<&Rates> {
+        self.countries
+            .get(&country.to_string())
+            .and_then(|c| match destination {
+                Some(d) => c.destinations.get(&d.to_string()),
+                None => c.domestic.as_ref(),
+            })
+    }
+
+    fn mileage(&self, country: &Country) -> Option<&Mileage> {
+        self.countries
+            .get(&country.to_string())
+            .and_then(|c| c.mileage.as_ref())
+    }
+
+    fn home_office(&self, country: &Country) -> Option<&HomeOffice> {
+        self.countries
+            .get(&country.to_string())
+            .and_then(|c| c.home_office.as_ref())
+    }
+
+    /// Replace our rates with the ones in `other` for each country and destination it mentions.
+    fn merge(&mut self, other: RateSet) {
+        for (country, other) in other.countries {
+            match self.countries.get_mut(&country) {
+                Some(ours) => {
+                    if other.mileage.is_some() {
+                        ours.mileage = other.mileage;
+                    }
+                    if other.home_office.is_some() {
+                        ours.home_office = other.home_office;
+                    }
+                    if other.domestic.is_some() {
+                        ours.domestic = other.domestic;
+                    }
+                    ours.destinations.extend(other.destinations);
+                }
+                None => {
+                    self.countries.insert(country, other);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for RateSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "'{}'", self.name)?;
+        match (self.valid_from, self.valid_until) {
+            (Some(from), Some(until)) => write!(
+                f,
+                " (valid from {} to {})",
+                to_date_string(&from),
+                to_date_string(&until)
+            ),
+            (Some(from), None) => write!(f, " (valid from {})", to_date_string(&from)),
+            (None, Some(until)) => write!(f, " (valid until {})", to_date_string(&until)),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Default for RateTable {
+    fn default() -> Self {
+        serde_yaml::from_str(DEFAULT_RATES).expect("built-in rates to be valid")
+    }
+}
+
+impl RateTable {
+    pub fn from_file(path: &Path) -> Result<RateTable, Error> {
+        let table: RateTable = serde_yaml::from_reader(
+            File::open(path)
+                .with_context(|_| format!("Could not open rates file at '{}'", path.display()))?,
+        )
+        .with_context(|_| format!("Could not deserialize rates file at '{}'", path.display()))?;
+        if table.version != VERSION {
+            bail!(
+                "Rates file at '{}' has version {}, but only version {} is supported.",
+                path.display(),
+                table.version,
+                VERSION
+            );
+        }
+        Ok(table)
+    }
+
+    /// Merge the rate sets of `other` into ours. Sets with the same name have their countries and
+    /// destinations replaced by the ones in `other`, all other sets are added.
+    pub fn merged(mut self, other: RateTable) -> RateTable {
+        for other in other.rate_sets {
+            match self.rate_sets.iter_mut().find(|s| s.name == other.name) {
+                Some(ours) => ours.merge(other),
+                None => self.rate_sets.push(other),
+            }
+        }
+        self
+    }
+
+    /// All destinations of all countries, sorted by name and without duplicates.
+    pub fn destinations(&self) -> Vec<Destination> {
+        self.rate_sets
+            .iter()
+            .flat_map(|s| s.countries.values())
+            .flat_map(|c| c.destinations.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(Destination)
+            .collect()
+    }
+
+    /// The destination named like `destination` in this table, or an error suggesting similar ones.
+    pub fn destination(&self, destination: &Destination) -> Result<Destination, Error> {
+        let known = self.destinations();
+        if let Some(d) = known.iter().find(|d| d.matches(destination)) {
+            return Ok(d.clone());
+        }
+        let name = destination.to_string();
+        let country = normalized(name.split('-').next().unwrap_or(&name));
+        let similar: Vec<_> = known
+            .iter()
+            .filter(|d| !country.is_empty() && normalized(&d.to_string()).starts_with(&country))
+            .map(|d| format!("'{}'", d))
+            .collect();
+        if similar.is_empty() {
+            bail!(
+                "Invalid destination: '{}'. Destinations are named like 'India-Other' or 'USA-New York'.",
+                name
+            )
+        } else {
+            bail!(
+                "Invalid destination: '{}'. Did you mean one of {}?",
+                name,
+                similar.join(", ")
+            )
+        }
+    }
+
+    fn latest_valid_at<'a, T: 'a>(
+        &'a self,
+        date: &Date<Utc>,
+        get: impl Fn(&'a RateSet) -> Option<T>,
+    ) -> Option<(&'a RateSet, T)> {
+        self.rate_sets
+            .iter()
+            .filter(|s| s.is_valid_at(date))
+            .filter_map(|s| get(s).map(|v| (s, v)))
+            .max_by_key(|(s, _)| s.valid_from)
+    }
+
+    /// The rate set providing per-diems for the given country and destination at `date`.
+    pub fn rate_set(
+        &self,
+        country: &Country,
+        destination: Option<&Destination>,
+        date: &Date<Utc>,
+    ) -> Option<&RateSet> {
+        self.latest_valid_at(date, |s| s.rates(country, destination))
+            .map(|(s, _)| s)
+    }
+
+    pub fn rates(
+        &self,
+        country: &Country,
+        destination: Option<&Destination>,
+        date: &Date<Utc>,
+    ) -> Option<&Rates> {
+        self.latest_valid_at(date, |s| s.rates(country, destination))
+            .map(|(_, r)| r)
+    }
+
+    /// The rate set providing the mileage rate for the given country at `date`.
+    pub fn mileage_rate_set(&self, country: &Country, date: &Date<Utc>) -> Option<&RateSet> {
+        self.latest_valid_at(date, |s| s.mileage(country))
+            .map(|(s, _)| s)
+    }
+
+    pub fn mileage(&self, country: &Country, date: &Date<Utc>) -> Result<&Mileage, Error> {
+        self.latest_valid_at(date, |s| s.mileage(country))
+            .map(|(_, m)| m)
+            .ok_or_else(|| {
+                format_err!(
+                    "There is no mileage rate for {} on {}.",
+                    country,
+                    to_date_string(date)
+                )
+            })
+    }
+
+    /// The rate set providing the home-office rate for the given country at `date`.
+    pub fn home_office_rate_set(&self, country: &Country, date: &Date<Utc>) -> Option<&RateSet> {
+        self.latest_valid_at(date, |s| s.home_office(country))
+            .map(|(s, _)| s)
+    }
+
+    pub fn home_office(&self, country: &Country, date: &Date<Utc>) -> Result<&HomeOffice, Error> {
+        self.latest_valid_at(date, |s| s.home_office(country))
+            .map(|(_, h)| h)
+            .ok_or_else(|| {
+                format_err!(
+                    "There is no home-office rate for {} on {}.",
+                    country,
+                    to_date_string(date)
+                )
+            })
+    }
+
+    /// The overnight flat rate for the given country and destination at `date`.
+    pub fn accommodation(
+        &self,
+        country: &Country,
+        destination: Option<&Destination>,
+        date: &Date<Utc>,
+    ) -> Result<u32, Error> {
+        self.rates(country, destination, date)
+            .and_then(|rates| rates.accommodation)
+            .ok_or_else(|| {
+                format_err!(
+                    "There is no accommodation rate for {} on {}.",
+                    place(country, destination),
+                    to_date_string(date)
+                )
+            })
+    }
+
+    pub fn amount(
+        &self,
+        country: &Country,
+        destination: Option<&Destination>,
+        kind: &Kind,
+        date: &Date<Utc>,
+    ) -> Result<u32, Error> {
+        let place = place(country, destination);
+        let rates = self.rates(country, destination, date).ok_or_else(|| {
+            format_err!(
+                "There are no per-diem rates for {} on {}.",
+                place,
+                to_date_string(date)
+            )
+        })?;
+        rates.get(kind).ok_or_else(|| {
+            format_err!(
+                "There is no per-diem rate for '{}' in {} on {}. Valid kinds are {}.",
+                kind.name(),
+                place,
+                to_date_string(date),
+                rates.kinds().join(", ")
+            )
+        })
+    }
+}
+
+/// Describes a destination relative to the home country, like 'India-Other for Germany'.
+fn place(country: &Country, destination: Option<&Destination>) -> String {
+    format!(
+        "{}{}",
+        destination
+            .map(|d| format!("{} for ", d))
+            .unwrap_or_default(),
+        country
+    )
+}
+
+/// Split all spans so that each one lies within a single rate set as determined by `rate_set_at`,
+/// and return them along with a description of each rate set used, in order of first use.
+/// The description names the place the rate set was used for, as returned by `place_at`, if any.
+pub(crate) fn split_by_rate_set<'a>(
+    spans: Vec<Span>,
+    rate_set_at: impl Fn(&Date<Utc>) -> Option<&'a RateSet>,
+    place_at: impl Fn(&Date<Utc>) -> Option<String>,
+) -> (Vec<Span>, Vec<String>) {
+    let spans: Vec<_> = spans
+        .into_iter()
+        .flat_map(|span| span.split_by(|day| rate_set_at(day).map(|s| &s.name)))
+        .collect();
+    let mut used = Vec::new();
+    for span in &spans {
+        let day = span.first_day();
+        let set = match (rate_set_at(day), place_at(day)) {
+            (Some(set), Some(place)) => format!("{} for {}", set, place),
+            (Some(set), None) => set.to_string(),
+            (None, _) => continue,
+        };
+        if !used.contains(&set) {
+            used.push(set);
+        }
+    }
+    (spans, used)
+}
@@ -0,0 +1,103 @@
+use chrono::{Date, Utc};
+use failure::{Error, ResultExt};
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+use {from_date_string, to_date_string};
+
+/// The name of the file in the context directory which records the days posted so far.
+pub const FILE_NAME: &str = "record.jsonl";
+
+/// A single day of an expense which was posted successfully.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    #[serde(
+        serialize_with = "serialize_date",
+        deserialize_with = "deserialize_date"
+    )]
+    pub date: Date<Utc>,
+    /// The kind of expense, like 'home-office'.
+    pub kind: String,
+    /// The name of the context used to post it.
+    #[serde(default)]
+    pub context: String,
+    pub project: String,
+    /// In cents of the home country's currency.
+    pub amount: i32,
+    /// The external identifier of the expense the day belongs to, if it has one.
+    #[serde(
+        rename = "externalID",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub external_id: Option<String>,
+}
+
+fn serialize_date<S: Serializer>(date: &Date<Utc>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&to_date_string(date))
+}
+
+fn deserialize_date<'de, D: Deserializer<'de>>(d: D) -> Result<Date<Utc>, D::Error> {
+    from_date_string(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+/// The days posted so far, as recorded locally in a file with one JSON object per line.
+#[derive(Default)]
+pub struct Record {
+    file: Option<PathBuf>,
+    entries: Vec<Entry>,
+}
+
+impl Record {
+    /// Read the record at `path`, which is empty if the file does not exist yet.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut entries = Vec::new();
+        if path.is_file() {
+            let file = File::open(path)
+                .with_context(|_| format!("Could not open record at '{}'", path.display()))?;
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                entries.push(serde_json::from_str(&line).with_context(|_| {
+                    format!(
+                        "Could not parse line {} of the record at '{}'",
+                        number + 1,
+                        path.display()
+                    )
+                })?);
+            }
+        }
+        Ok(Record {
+            file: Some(path.to_owned()),
+            entries,
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The file the record was read from, and new entries should be appended to.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_ref().map(PathBuf::as_path)
+    }
+}
+
+/// Append `entries` to the record at `path`, creating the file if needed.
+pub fn append(path: &Path, entries: &[Entry]) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|_| format!("Could not open record at '{}'", path.display()))?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    Ok(())
+}
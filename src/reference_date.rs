@@ -0,0 +1,59 @@
+use chrono::prelude::*;
+use failure::Error;
+use from_date_string;
+use time::Duration;
+
+/// Parse a reference date, which can be
+/// * a date like '2018-09-25'
+/// * 'today' or 'yesterday'
+/// * 'last-week' or 'next-week', the same weekday a week earlier or later
+/// * a number of days or weeks relative to `today`, like '-2w', '+1w' or '-3d'
+/// * an ISO week like '2018-W39', which is the Monday of that week
+///
+/// `today` should be the date in the time zone of the user, as given by `Context::today()`.
+pub fn parse_reference_date(s: &str, today: Date<Utc>) -> Result<Date<Utc>, Error> {
+    let s = s.trim();
+    let offset = match s.to_ascii_lowercase().as_str() {
+        "today" => Duration::zero(),
+        "yesterday" => Duration::days(-1),
+        "last-week" => Duration::weeks(-1),
+        "next-week" => Duration::weeks(1),
+        relative if relative.starts_with(|c| c == '-' || c == '+') => parse_offset(relative)
+            .ok_or_else(|| {
+                format_err!(
+                    "Invalid relative date '{}' - use something like '-2w' or '+3d'",
+                    s
+                )
+            })?,
+        _ => {
+            return match s.find(|c| c == 'W' || c == 'w') {
+                Some(pos) => parse_iso_week(&s[..pos], &s[pos + 1..]).ok_or_else(|| {
+                    format_err!("Invalid ISO week '{}' - use something like '2018-W39'", s)
+                }),
+                None => from_date_string(s),
+            }
+        }
+    };
+    today
+        .checked_add_signed(offset)
+        .ok_or_else(|| format_err!("The date '{}' is out of range", s))
+}
+
+fn parse_offset(s: &str) -> Option<Duration> {
+    let unit = s.chars().last()?;
+    let amount: i32 = s[..s.len() - unit.len_utf8()]
+        .trim_start_matches('+')
+        .parse()
+        .ok()?;
+    match unit {
+        'd' => Some(Duration::days(amount.into())),
+        'w' => Some(Duration::weeks(amount.into())),
+        _ => None,
+    }
+}
+
+fn parse_iso_week(year: &str, week: &str) -> Option<Date<Utc>> {
+    let year: i32 = year.trim_end_matches('-').parse().ok()?;
+    let week: u32 = week.parse().ok()?;
+    NaiveDate::from_isoywd_opt(year, week, Weekday::Mon).map(|d| Date::from_utc(d, Utc))
+}
@@ -0,0 +1,100 @@
+use chrono::{Date, Datelike, NaiveDate, Utc};
+use failure::Error;
+use perdiem::KIND_PREFIX;
+use record::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use to_date_string;
+use Context;
+
+/// A new window starts after at least this many days without per-diems at the same workplace.
+const INTERRUPTION_DAYS: i64 = 28;
+
+/// Check the `claimed` per-diems against the German three-month rule, which limits per-diems at the same
+/// workplace to the first three months. Days of the same context and project in the local record are considered
+/// to be at the same workplace. Returns notes for days outside of the window if `ignore` is set, and fails otherwise.
+pub(crate) fn check(ctx: &Context, claimed: &[Entry], ignore: bool) -> Result<Vec<String>, Error> {
+    let mut amounts = BTreeMap::new();
+    for entry in ctx.record.entries().iter().chain(claimed).filter(|e| {
+        e.kind.starts_with(KIND_PREFIX) && e.context == ctx.name && e.project == ctx.user.project
+    }) {
+        *amounts.entry(entry.date).or_insert(0) += entry.amount;
+    }
+    let claimed_days: BTreeSet<_> = claimed
+        .iter()
+        .filter(|e| e.amount > 0)
+        .map(|e| e.date)
+        .collect();
+
+    let mut outside: BTreeMap<Date<Utc>, Vec<Date<Utc>>> = BTreeMap::new();
+    let mut window: Option<(Date<Utc>, Date<Utc>)> = None;
+    for (date, amount) in amounts {
+        if amount <= 0 {
+            continue;
+        }
+        let start = match window {
+            Some((start, previous)) if (date - previous).num_days() - 1 < INTERRUPTION_DAYS => {
+                start
+            }
+            _ => date,
+        };
+        window = Some((start, date));
+        if claimed_days.contains(&date) && date >= three_months_after(&start) {
+            outside.entry(start).or_insert_with(Vec::new).push(date);
+        }
+    }
+
+    let mut notes = Vec::new();
+    for (start, days) in outside {
+        let message = format!(
+            "Per-diems at the same workplace are limited to the first three months, but {} {} outside of the window from {} to {}. \
+             It started with the first per-diem of context '{}' and project '{}' in the local record, ends the day before the same date \
+             three months later, and only starts anew after at least four weeks without per-diems.",
+            describe(&days),
+            if days.len() == 1 { "is" } else { "are" },
+            to_date_string(&start),
+            to_date_string(&three_months_after(&start).pred()),
+            ctx.name,
+            ctx.user.project
+        );
+        if !ignore {
+            bail!(
+                "{} Use --ignore-three-month-rule to post nonetheless, for example if your workplace changed.",
+                message
+            );
+        }
+        notes.push(message);
+    }
+    Ok(notes)
+}
+
+/// The same date three months later, or the first day of the following month if that date does not exist.
+fn three_months_after(date: &Date<Utc>) -> Date<Utc> {
+    let months = date.year() * 12 + date.month0() as i32 + 3;
+    let (year, month) = (months / 12, months % 12 + 1);
+    // Only months with less than 31 days can miss the date, and none of them is December.
+    let naive = NaiveDate::from_ymd_opt(year, month as u32, date.day())
+        .unwrap_or_else(|| NaiveDate::from_ymd(year, month as u32 + 1, 1));
+    Date::from_utc(naive, Utc)
+}
+
+/// Describes consecutive days as range, like '2019-06-17 to 2019-06-21, 2019-06-24'.
+fn describe(days: &[Date<Utc>]) -> String {
+    let mut ranges: Vec<(Date<Utc>, Date<Utc>)> = Vec::new();
+    for day in days {
+        match ranges.last_mut() {
+            Some((_, to)) if to.succ() == *day => *to = *day,
+            _ => ranges.push((*day, *day)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(from, to)| {
+            if from == to {
+                to_date_string(from)
+            } else {
+                format!("{} to {}", to_date_string(from), to_date_string(to))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
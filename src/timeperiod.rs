@@ -1,64 +1,699 @@
-use failure::{bail, Error};
-use std::str::FromStr;
-use Weekday;
+use chrono::{Date, Datelike, NaiveDate, Utc};
+use failure::{bail, format_err, Error};
+use holidays::{describe, Holidays};
+use std::{fmt, str::FromStr};
+use time::Duration;
+use {from_date_string, to_date_string, Context, Weekday};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum TimePeriod {
     Weekdays,
     SingleDay(Weekday),
-    DayRange { from: Weekday, to: Weekday },
+    DayRange {
+        from: Weekday,
+        to: Weekday,
+    },
+    /// Single days, filed day by day unless they are two consecutive days.
     Days(Vec<Weekday>),
+    /// Ranges of days and 'weekdays', minus the days after 'except', which depend on the start of the week.
+    Selection(DaySelection),
+    Date(Date<Utc>),
+    DateRange {
+        from: Date<Utc>,
+        to: Date<Utc>,
+    },
+    /// The given days of a month like (2018, 10), or of the month of the reference date.
+    /// Without days, it's the working days as in `Weekdays`.
+    Month {
+        month: Option<(i32, u32)>,
+        days: Option<DaySelection>,
+    },
+    /// The given days of a number of weeks, starting with the week of the reference date.
+    /// Without days, it's the working days as in `Weekdays`.
+    Weeks {
+        weeks: u32,
+        days: Option<DaySelection>,
+    },
+}
+
+/// Days of the week which are resolved once the start of the week is known.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DaySet {
+    /// The first five days of the week.
+    Weekdays,
+    /// All days from the first to the second one, which must not wrap around the start of the week.
+    /// Single days start and end on the same day.
+    Range(Weekday, Weekday),
+}
+
+/// The union of the included days of the week, without the excluded ones.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct DaySelection {
+    pub include: Vec<DaySet>,
+    pub exclude: Vec<DaySet>,
+}
+
+/// All days of the week, starting with `first`.
+fn week_starting(first: Weekday) -> Vec<Weekday> {
+    (0..7)
+        .map(|offset| Weekday::ALL[(first.numerical() as usize + offset) % 7])
+        .collect()
+}
+
+impl DaySet {
+    /// The days of the week starting on `first`, in order.
+    fn weekdays(&self, first: Weekday) -> Result<Vec<Weekday>, Error> {
+        let week = week_starting(first);
+        Ok(match self {
+            DaySet::Weekdays => week[..5].to_vec(),
+            DaySet::Range(from, to) => {
+                let position = |day| week.iter().position(|d| d == day).expect("all days");
+                let (start, end) = (position(from), position(to));
+                if start > end {
+                    bail!(
+                        "The range from {} to {} wraps around the start of the week on {}",
+                        from,
+                        to,
+                        first
+                    );
+                }
+                week[start..=end].to_vec()
+            }
+        })
+    }
+}
+
+impl fmt::Display for DaySet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            DaySet::Weekdays => f.write_str("weekdays"),
+            DaySet::Range(from, to) if from == to => write!(f, "{}", from),
+            DaySet::Range(from, to) => write!(f, "{}-{}", from, to),
+        }
+    }
+}
+
+impl DaySelection {
+    /// The selected days of the week starting on `first`, in order.
+    pub fn weekdays(&self, first: Weekday) -> Result<Vec<Weekday>, Error> {
+        let resolve = |sets: &[DaySet]| -> Result<Vec<Weekday>, Error> {
+            let mut days = Vec::new();
+            for set in sets {
+                days.extend(set.weekdays(first)?);
+            }
+            Ok(days)
+        };
+        let (include, exclude) = (resolve(&self.include)?, resolve(&self.exclude)?);
+        let days: Vec<_> = week_starting(first)
+            .into_iter()
+            .filter(|day| include.contains(day) && !exclude.contains(day))
+            .collect();
+        if days.is_empty() {
+            bail!("There are no days left in '{}'", self);
+        }
+        Ok(days)
+    }
+}
+
+impl fmt::Display for DaySelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let join = |sets: &[DaySet]| {
+            sets.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        f.write_str(&join(&self.include))?;
+        if !self.exclude.is_empty() {
+            write!(f, " except {}", join(&self.exclude))?;
+        }
+        Ok(())
+    }
 }
 
 impl FromStr for TimePeriod {
     type Err = Error;
 
+    /// Parses months or weeks, see `parse_month_or_weeks()`, dates, see `parse_dates()`, or a comma separated list of days, ranges of days like 'mon-wed'
+    /// and 'weekdays', optionally followed by 'except' and a list of days to leave out, like 'weekdays except wed'.
+    /// 'weekdays' are the first five days of the week. Ranges and 'weekdays' are resolved once the start of the week is known.
     fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
         use self::TimePeriod::*;
-        let mut words = s.trim().split('-').map(str::trim).filter(|s| s.len() != 0);
-
-        Ok(match (words.next(), words.next(), words.next()) {
-            (Some(first), None, None) => match first {
-                "weekdays" => Weekdays,
-                s => {
-                    let mut commas: Vec<_> = s
-                        .split(',')
-                        .map(str::trim)
-                        .filter(|s| s.len() != 0)
-                        .map(Weekday::from_str)
-                        .collect::<Result<_, _>>()?;
-                    commas.sort_by_key(|d| d.numerical());
-                    let days = commas.into_iter().fold(Vec::new(), |mut acc, d| {
-                        if !acc.iter().any(|od| *od == d) {
-                            acc.push(d)
+        if let Some(period) = parse_month_or_weeks(s)? {
+            return Ok(period);
+        }
+        if let Some(dates) = parse_dates(s)? {
+            return Ok(dates);
+        }
+
+        if s.trim().eq_ignore_ascii_case("weekdays") {
+            return Ok(Weekdays);
+        }
+
+        let mut selection = DaySelection::default();
+        let mut include = true;
+        let mut only_single_days = true;
+        let tokens = tokenize(s);
+        let mut tokens = tokens.iter().peekable();
+        while let Some(token) = tokens.next() {
+            let days = match token.text {
+                "," | "-" => continue,
+                t if t.eq_ignore_ascii_case("except") => {
+                    if !include {
+                        return Err(token.error(s, "Only a single 'except' is allowed"));
+                    }
+                    if selection.include.is_empty() {
+                        return Err(token.error(s, "Expected days before 'except'"));
+                    }
+                    include = false;
+                    only_single_days = false;
+                    continue;
+                }
+                t if t.eq_ignore_ascii_case("weekdays") => DaySet::Weekdays,
+                _ => {
+                    let from = token.weekday(s)?;
+                    let mut to = from;
+                    let mut num_days = 1;
+                    while let Some(&&Token { text: "-", .. }) = tokens.peek() {
+                        while let Some(&&Token { text: "-", .. }) = tokens.peek() {
+                            tokens.next();
                         }
-                        acc
-                    });
-                    match days.as_slice() {
-                        [] => bail!("Didn't see a single weekday in '{}'", s),
-                        &[d] => SingleDay(d),
-                        &[from, to] if from.numerical() == to.numerical() - 1 => {
-                            DayRange { from, to }
+                        match tokens.peek() {
+                            Some(next)
+                                if next.text != ","
+                                    && !next.text.eq_ignore_ascii_case("except") =>
+                            {
+                                num_days += 1;
+                                if num_days > 2 {
+                                    return Err(next.error(
+                                        s,
+                                        "More than two days separated by '-' are not allowed",
+                                    ));
+                                }
+                                to = next.weekday(s)?;
+                                tokens.next();
+                            }
+                            _ => break,
                         }
-                        _ => Days(days.clone()),
                     }
+                    DaySet::Range(from, to)
                 }
+            };
+            only_single_days &= match days {
+                DaySet::Range(from, to) => from == to,
+                DaySet::Weekdays => false,
+            };
+            if include {
+                selection.include.push(days);
+            } else {
+                selection.exclude.push(days);
+            }
+        }
+        if !include && selection.exclude.is_empty() {
+            bail!("Expected days after 'except' in '{}'", s.trim());
+        }
+        if selection.include.is_empty() {
+            bail!("Didn't see a single weekday in '{}'", s.trim());
+        }
+
+        if only_single_days {
+            let days: Vec<Weekday> = Weekday::ALL
+                .iter()
+                .filter(|day| selection.include.contains(&DaySet::Range(**day, **day)))
+                .cloned()
+                .collect();
+            return Ok(match days.as_slice() {
+                &[day] => SingleDay(day),
+                _ => Days(days),
+            });
+        }
+        Ok(
+            match (selection.include.as_slice(), selection.exclude.is_empty()) {
+                (&[DaySet::Range(from, to)], true) => DayRange { from, to },
+                _ => Selection(selection),
             },
-            (Some(first), Some(second), None) => {
-                let from = first.parse()?;
-                let to = second.parse()?;
-                if from == to {
-                    SingleDay(from)
-                } else if from.is_after(&to) {
-                    bail!("Day '{}' must be temporally before '{}', but came after. Write '{}-{}' instead.", from, to, to, from)
-                } else {
-                    DayRange { from, to }
+        )
+    }
+}
+
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+}
+
+impl<'a> Token<'a> {
+    fn weekday(&self, input: &str) -> Result<Weekday, Error> {
+        self.text
+            .parse()
+            .map_err(|_| self.error(input, "Expected a weekday like 'mon' or 'weekdays'"))
+    }
+
+    /// An error message which marks this token in `input`.
+    fn error(&self, input: &str, message: &str) -> Error {
+        format_err!(
+            "{}:\n{}\n{}{}",
+            message,
+            input,
+            " ".repeat(input[..self.start].chars().count()),
+            "^".repeat(self.text.chars().count())
+        )
+    }
+}
+
+/// Split `s` into words, ',' and '-', dropping whitespace.
+fn tokenize<'a>(s: &'a str) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    for (pos, c) in s.char_indices() {
+        let is_separator = c.is_whitespace() || c == ',' || c == '-';
+        if is_separator {
+            if let Some(start) = word_start.take() {
+                tokens.push(Token {
+                    text: &s[start..pos],
+                    start,
+                });
+            }
+            if !c.is_whitespace() {
+                tokens.push(Token {
+                    text: &s[pos..pos + 1],
+                    start: pos,
+                });
+            }
+        } else if word_start.is_none() {
+            word_start = Some(pos);
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push(Token {
+            text: &s[start..],
+            start,
+        });
+    }
+    tokens
+}
+
+/// Parse 'month', a month like '2018-10' or a number of weeks like 'weeks:3', optionally followed by the days
+/// to use, like 'month mon-thu'. The working days of the week are used by default.
+/// Returns `None` if `s` doesn't start with any of these.
+fn parse_month_or_weeks(s: &str) -> Result<Option<TimePeriod>, Error> {
+    let s = s.trim();
+    let (first, rest) = match s.find(char::is_whitespace) {
+        Some(pos) => (&s[..pos], Some(&s[pos..])),
+        None => (s, None),
+    };
+    let days = || -> Result<Option<DaySelection>, Error> {
+        match rest {
+            Some(rest) => match rest.parse::<TimePeriod>()? {
+                TimePeriod::Weekdays => Ok(None),
+                period => period.into_selection().map(Some).ok_or_else(|| {
+                    format_err!(
+                        "Only days like 'mon-thu' may follow '{}', but got '{}'",
+                        first,
+                        rest.trim()
+                    )
+                }),
+            },
+            None => Ok(None),
+        }
+    };
+
+    if first.eq_ignore_ascii_case("month") {
+        return Ok(Some(TimePeriod::Month {
+            month: None,
+            days: days()?,
+        }));
+    }
+    if let (Some(prefix), Some(weeks)) = (first.get(..6), first.get(6..)) {
+        if prefix.eq_ignore_ascii_case("weeks:") {
+            let weeks = match weeks.parse() {
+                Ok(weeks) if weeks > 0 => weeks,
+                _ => bail!(
+                    "Expected a positive number of weeks like 'weeks:3', but got '{}'",
+                    first
+                ),
+            };
+            return Ok(Some(TimePeriod::Weeks {
+                weeks,
+                days: days()?,
+            }));
+        }
+    }
+    let mut parts = first.split('-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(year), Some(month), None)
+            if year.len() == 4
+                && year
+                    .chars()
+                    .chain(month.chars())
+                    .all(|c| c.is_ascii_digit()) =>
+        {
+            let (year, month) = (year.parse()?, month.parse()?);
+            if month < 1 || month > 12 {
+                bail!("Month {} does not exist in '{}'", month, first);
+            }
+            Ok(Some(TimePeriod::Month {
+                month: Some((year, month)),
+                days: days()?,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Parse a single date like '2018-09-27' or an inclusive range of dates like '2018-09-27..2018-10-02',
+/// returning `None` if `s` doesn't look like a date.
+fn parse_dates(s: &str) -> Result<Option<TimePeriod>, Error> {
+    use self::TimePeriod::*;
+    let s = s.trim();
+    if !s.starts_with(|c: char| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let mut dates = s.split("..").map(str::trim);
+    Ok(Some(match (dates.next(), dates.next(), dates.next()) {
+        (Some(date), None, None) => Date(from_date_string(date)?),
+        (Some(from), Some(to), None) => {
+            let (from, to) = (from_date_string(from)?, from_date_string(to)?);
+            if from == to {
+                Date(from)
+            } else if from > to {
+                bail!(
+                    "Date '{}' must be before '{}', but came after. Write '{}..{}' instead.",
+                    to_date_string(&from),
+                    to_date_string(&to),
+                    to_date_string(&to),
+                    to_date_string(&from)
+                )
+            } else {
+                DateRange { from, to }
+            }
+        }
+        _ => bail!(
+            "More than two dates separated by '..' are not allowed in '{}'",
+            s
+        ),
+    }))
+}
+
+#[derive(Clone)]
+pub(crate) enum Span {
+    Day(Date<Utc>),
+    /// All days from `from` to `to`, and the holidays which were left out of the original range.
+    Range {
+        from: Date<Utc>,
+        to: Date<Utc>,
+        skipped: Vec<(Date<Utc>, String)>,
+    },
+}
+
+impl Span {
+    pub fn first_day(&self) -> &Date<Utc> {
+        match self {
+            Span::Day(day) => day,
+            Span::Range { from, .. } => from,
+        }
+    }
+
+    pub fn created(&self) -> String {
+        to_date_string(self.first_day())
+    }
+
+    pub fn range(from: Date<Utc>, to: Date<Utc>) -> Span {
+        Span::Range {
+            from,
+            to,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Split a range into consecutive ranges whose days all share the same `key`.
+    /// Skipped holidays are kept by the first range.
+    pub fn split_by<K: PartialEq>(self, mut key: impl FnMut(&Date<Utc>) -> K) -> Vec<Span> {
+        let (from, to, skipped) = match self {
+            Span::Day(_) => return vec![self],
+            Span::Range { from, to, skipped } => (from, to, skipped),
+        };
+        let mut spans = Vec::new();
+        let mut start = (from, key(&from));
+        let mut day = from;
+        while day < to {
+            let next = day.succ();
+            let next_key = key(&next);
+            if next_key != start.1 {
+                spans.push(Span::range(start.0, day));
+                start = (next, next_key);
+            }
+            day = next;
+        }
+        spans.push(Span::range(start.0, to));
+        if let Some(span) = spans.first_mut() {
+            span.set_skipped(skipped);
+        }
+        spans
+    }
+
+    /// Split a range into consecutive ranges without any of the given holidays.
+    /// Skipped holidays are kept by the first range.
+    pub fn without_holidays(self, holidays: &Holidays) -> Result<Vec<Span>, Error> {
+        self.only_on(&Weekday::ALL, holidays)
+    }
+
+    /// Split a range into consecutive ranges of the given weekdays without any of the given holidays.
+    /// Skipped holidays are kept by the first range.
+    pub fn only_on(self, weekdays: &[Weekday], holidays: &Holidays) -> Result<Vec<Span>, Error> {
+        let (from, to) = match self {
+            Span::Day(_) => return Ok(vec![self]),
+            Span::Range { from, to, .. } => (from, to),
+        };
+        let mut spans = Vec::new();
+        let mut skipped = Vec::new();
+        let mut start = None;
+        let mut day = from;
+        loop {
+            let holiday = if weekdays.contains(&Weekday::of(&day)) {
+                holidays.on(&day).map(Some)
+            } else {
+                Some(None)
+            };
+            match holiday {
+                Some(name) => {
+                    if let Some(start) = start.take() {
+                        spans.push(Span::range(start, day.pred()));
+                    }
+                    if let Some(name) = name {
+                        skipped.push((day, name));
+                    }
+                }
+                None => {
+                    start.get_or_insert(day);
                 }
             }
-            _ => bail!(
-                "More than two days separated by '-' are not allowed in '{}'",
-                s
+            if day >= to {
+                break;
+            }
+            day = day.succ();
+        }
+        if let Some(start) = start {
+            spans.push(Span::range(start, to));
+        }
+        match spans.first_mut() {
+            Some(span) => span.set_skipped(skipped),
+            None if skipped.is_empty() => bail!(
+                "There is no {} from {} to {}",
+                weekdays
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                to_date_string(&from),
+                to_date_string(&to)
+            ),
+            None => bail!(
+                "All days from {} to {} are holidays: {}",
+                to_date_string(&from),
+                to_date_string(&to),
+                describe(&skipped)
             ),
+        }
+        Ok(spans)
+    }
+
+    fn set_skipped(&mut self, holidays: Vec<(Date<Utc>, String)>) {
+        if let Span::Range { skipped, .. } = self {
+            *skipped = holidays;
+        }
+    }
+
+    /// All days from the first to the last day of the span.
+    pub fn days(&self) -> Vec<Date<Utc>> {
+        match self {
+            Span::Day(day) => vec![*day],
+            Span::Range { from, to, .. } => {
+                let mut days = vec![*from];
+                while days[days.len() - 1] < *to {
+                    let next = days[days.len() - 1].succ();
+                    days.push(next);
+                }
+                days
+            }
+        }
+    }
+
+    pub fn num_days(&self) -> u32 {
+        match self {
+            Span::Day(_) => 1,
+            Span::Range { from, to, .. } => {
+                let num_days = (*to - *from).num_days() + 1;
+                assert!(num_days > 0, "to-date must be larger than from-date");
+                num_days as u32
+            }
+        }
+    }
+
+    pub fn comment(&self, ctx: &Context) -> String {
+        match self {
+            Span::Day(_) => ctx.comment.clone().unwrap_or_default(),
+            Span::Range { from, to, skipped } => {
+                let comment = if from == to {
+                    to_date_string(from)
+                } else {
+                    format!("{} to {}", to_date_string(from), to_date_string(to))
+                };
+                let comment = ctx
+                    .comment
+                    .as_ref()
+                    .map(|custom| format!("{}: {}", comment, custom))
+                    .unwrap_or(comment);
+                if skipped.is_empty() {
+                    comment
+                } else {
+                    format!("{} (skipped holidays: {})", comment, describe(skipped))
+                }
+            }
+        }
+    }
+}
+
+impl TimePeriod {
+    /// The days of the week this period consists of, or `None` if it is made of dates or `Weekdays`,
+    /// which are the default days of months and weeks.
+    fn into_selection(self) -> Option<DaySelection> {
+        use self::TimePeriod::*;
+        let single_days = |days: Vec<Weekday>| DaySelection {
+            include: days
+                .into_iter()
+                .map(|day| DaySet::Range(day, day))
+                .collect(),
+            exclude: Vec::new(),
+        };
+        Some(match self {
+            SingleDay(day) => single_days(vec![day]),
+            DayRange { from, to } => DaySelection {
+                include: vec![DaySet::Range(from, to)],
+                exclude: Vec::new(),
+            },
+            Days(days) => single_days(days),
+            Selection(selection) => selection,
+            Weekdays | Date(_) | DateRange { .. } | Month { .. } | Weeks { .. } => return None,
+        })
+    }
+
+    pub(crate) fn into_spans(self, ctx: &Context) -> Result<Vec<Span>, Error> {
+        self.into_spans_without(ctx, &ctx.holidays)
+    }
+
+    /// All days of this period, including holidays.
+    pub(crate) fn into_days(self, ctx: &Context) -> Result<Vec<Date<Utc>>, Error> {
+        Ok(self
+            .into_spans_without(ctx, &Holidays::default())?
+            .iter()
+            .flat_map(Span::days)
+            .collect())
+    }
+
+    /// The spans of this period, leaving out `holidays`.
+    fn into_spans_without(self, ctx: &Context, holidays: &Holidays) -> Result<Vec<Span>, Error> {
+        use self::TimePeriod::*;
+
+        let week_start = ctx.start_of_reference_week()?;
+        let start_day = Weekday::of(&week_start);
+        let working_days = week_starting(start_day)[..5].to_vec();
+        let range = |from: Weekday, to: Weekday| -> Result<Span, Error> {
+            DaySet::Range(from, to).weekdays(start_day)?;
+            Ok(Span::range(
+                from.to_date_from(&week_start)?,
+                to.to_date_from(&week_start)?,
+            ))
+        };
+        let dates = |days: &[Weekday]| -> Result<Vec<::chrono::Date<Utc>>, Error> {
+            let mut dates = days
+                .iter()
+                .map(|day| day.to_date_from(&week_start))
+                .collect::<Result<Vec<_>, _>>()?;
+            dates.sort();
+            Ok(dates)
+        };
+        let selection_days = |days: Option<DaySelection>| match days {
+            Some(days) => days.weekdays(start_day),
+            None => Ok(working_days.clone()),
+        };
+        Ok(match self {
+            Weekdays => Span::range(week_start, week_start + Duration::days(4))
+                .without_holidays(holidays)?,
+            SingleDay(day) => vec![Span::Day(day.to_date_from(&week_start)?)],
+            DayRange { from, to } => range(from, to)?.without_holidays(holidays)?,
+            Selection(selection) => {
+                let mut spans = Vec::new();
+                let dates = dates(&selection.weekdays(start_day)?)?;
+                let mut start = 0;
+                for end in 0..dates.len() {
+                    if end + 1 < dates.len() && dates[end].succ() == dates[end + 1] {
+                        continue;
+                    }
+                    if start == end {
+                        spans.push(Span::Day(dates[start]));
+                    } else {
+                        spans.extend(
+                            Span::range(dates[start], dates[end]).without_holidays(holidays)?,
+                        );
+                    }
+                    start = end + 1;
+                }
+                spans
+            }
+            Date(date) => vec![Span::Day(date)],
+            DateRange { from, to } => Span::range(from, to).without_holidays(holidays)?,
+            Days(days) => match dates(&days)?.as_slice() {
+                &[from, to] if from.succ() == to => {
+                    Span::range(from, to).without_holidays(holidays)?
+                }
+                dates => dates.iter().map(|date| Span::Day(*date)).collect(),
+            },
+            Month { month, days } => {
+                let first = match month {
+                    Some((year, month)) => NaiveDate::from_ymd_opt(year, month, 1)
+                        .map(|d| ::chrono::Date::from_utc(d, Utc))
+                        .ok_or_else(|| format_err!("Invalid month {}-{:02}", year, month))?,
+                    None => {
+                        let today = ctx.reference_date.unwrap_or_else(|| ctx.today());
+                        today.with_day(1).expect("first day of month to exist")
+                    }
+                };
+                let last = match first.month() {
+                    12 => first
+                        .with_year(first.year() + 1)
+                        .and_then(|d| d.with_month(1)),
+                    month => first.with_month(month + 1),
+                }
+                .ok_or_else(|| format_err!("Failed to compute the end of the month"))?
+                .pred();
+                Span::range(first, last).only_on(&selection_days(days)?, holidays)?
+            }
+            Weeks { weeks, days } => {
+                let last = week_start
+                    .checked_add_signed(Duration::weeks(weeks.into()) - Duration::days(1))
+                    .ok_or_else(|| format_err!("Failed to compute the end of {} weeks", weeks))?;
+                Span::range(week_start, last).only_on(&selection_days(days)?, holidays)?
+            }
         })
     }
 }
@@ -0,0 +1,87 @@
+use chrono::{Date, DateTime, Utc};
+use failure::Error;
+use perdiem::Kind;
+use time::Duration;
+use timeperiod::Span;
+
+/// A business trip, from leaving home or the office until coming back there.
+pub struct Trip {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    overnight_stay: bool,
+}
+
+impl Trip {
+    /// If there is no `overnight_stay`, the trip may still pass midnight, but must end the day after it started.
+    pub fn new(
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        overnight_stay: bool,
+    ) -> Result<Self, Error> {
+        if end <= start {
+            bail!(
+                "The trip must end after it started, but it ended at {} which is not after {}",
+                end,
+                start
+            );
+        }
+        Ok(Trip {
+            start,
+            end,
+            overnight_stay,
+        })
+    }
+
+    /// The day the trip started on.
+    pub fn first_day(&self) -> Date<Utc> {
+        self.start.date()
+    }
+
+    /// Whether the trip started, ended or went on during `date`.
+    pub fn covers(&self, date: &Date<Utc>) -> bool {
+        self.start.date() <= *date && *date <= self.end.date()
+    }
+
+    /// Assign the kind of per-diem to each day of the trip, following the German rules:
+    /// Trips without overnight stay need to last more than 8 hours and are counted as day trip on the day
+    /// most of the time was spent. Otherwise, the first day is the arrival day, the last day is the departure day,
+    /// and all days in between are full days.
+    pub(crate) fn into_days(self) -> Result<Vec<(Span, Kind)>, Error> {
+        let (first, last) = (self.start.date(), self.end.date());
+        if first == last || !self.overnight_stay {
+            if last > first.succ() {
+                bail!(
+                    "A trip without overnight stay must end on the day after it started at the latest, but it ended on {}",
+                    last
+                );
+            }
+            let duration = self.end.signed_duration_since(self.start);
+            if duration <= Duration::hours(8) {
+                bail!(
+                    "A trip without overnight stay needs to last more than 8 hours, but it lasted {} hours and {} minutes",
+                    duration.num_hours(),
+                    duration.num_minutes() % 60
+                );
+            }
+            let midnight = last.and_hms(0, 0, 0);
+            let day = if midnight.signed_duration_since(self.start)
+                >= self.end.signed_duration_since(midnight)
+            {
+                first
+            } else {
+                last
+            };
+            return Ok(vec![(Span::Day(day), Kind::Daytrip)]);
+        }
+
+        let mut days = vec![(Span::Day(first), Kind::Arrival)];
+        let (from, to) = (first.succ(), last.pred());
+        if from == to {
+            days.push((Span::Day(from), Kind::FullDay));
+        } else if from < to {
+            days.push((Span::range(from, to), Kind::FullDay));
+        }
+        days.push((Span::Day(last), Kind::Departure));
+        Ok(days)
+    }
+}
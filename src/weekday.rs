@@ -33,14 +33,32 @@ impl FromStr for Weekday {
 }
 
 impl Weekday {
-    pub fn to_date_from(&self, reference: &Date<Utc>) -> Result<Date<Utc>, Error> {
-        reference
-            .checked_add_signed(Duration::days(self.numerical() as i64))
+    /// All days of the week, starting with Monday.
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// The day of the week of `date`.
+    pub fn of(date: &Date<Utc>) -> Weekday {
+        Weekday::ALL[date.weekday().num_days_from_monday() as usize]
+    }
+
+    /// The date of this day in the week starting at `week_start`, which may be any day of the week.
+    pub fn to_date_from(&self, week_start: &Date<Utc>) -> Result<Date<Utc>, Error> {
+        let offset = (self.numerical() + 7 - Weekday::of(week_start).numerical()) % 7;
+        week_start
+            .checked_add_signed(Duration::days(i64::from(offset)))
             .ok_or_else(|| {
                 format_err!(
                     "Failed to compute day {} from reference date {}",
                     self,
-                    reference
+                    week_start
                 )
             })
     }
@@ -63,6 +81,19 @@ impl Weekday {
     }
 }
 
+impl ::serde::Serialize for Weekday {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for Weekday {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let day = String::deserialize(deserializer)?;
+        day.parse().map_err(::serde::de::Error::custom)
+    }
+}
+
 impl fmt::Display for Weekday {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use self::Weekday::*;
@@ -65,11 +65,96 @@ WITH_FAILURE=1
                   expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" from-file --context  default "$fixture/transaction-list.json"
                 }
               )
+              (when "some of its expenses were posted before according to the ledger"
+                step "(adding the first expense to the ledger)"
+                echo '{"timestamp":"2019-06-15T09:00:00+00:00","context":"default","kind":"from-file","type":"create","payload":{"transactionList":[{"amount":600,"created":"1982-01-15","externalID":"expend-fdff26494cc77a68"}]},"response":{}}' > ledger.jsonl
+                it "skips the expenses posted before" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-from-yml-file-skipping-posted" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" from-file --context  default "$fixture/transaction-list.json"
+                }
+              )
+              (when "all of its expenses were posted before, but some were undone"
+                step "(adding the second expense and undoing the first one in the ledger)"
+                echo '{"timestamp":"2019-06-15T09:00:00+00:00","context":"default","kind":"from-file","type":"create","payload":{"transactionList":[{"amount":1200,"created":"1982-01-16","externalID":"expend-430c30c77fb0e13b"}]},"response":{}}' >> ledger.jsonl
+                echo '{"timestamp":"2019-06-16T09:00:00+00:00","context":"default","kind":"undo","type":"create","payload":{"transactionList":[{"amount":-600,"created":"1982-01-15"}]},"response":{},"undoes":1}' >> ledger.jsonl
+                it "posts the undone expenses only" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-from-yml-file-reposting-undone" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" from-file --context  default "$fixture/transaction-list.json"
+                }
+              )
+              (when "all of its expenses were posted before"
+                step "(posting the undone expense again)"
+                echo '{"timestamp":"2019-06-17T09:00:00+00:00","context":"default","kind":"from-file","type":"create","payload":{"transactionList":[{"amount":600,"created":"1982-01-15","externalID":"expend-fdff26494cc77a68"}]},"response":{}}' >> ledger.jsonl
+                it "fails gracefully" && {
+                  WITH_SNAPSHOT="$snapshot/failure-create-from-yml-file-all-posted" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" from-file --context  default "$fixture/transaction-list.json"
+                }
+              )
+              (when "its expenses were part of a post which did not complete"
+                step "(replacing the ledger by a pending entry)"
+                echo '{"timestamp":"2019-06-18T09:00:00+00:00","context":"default","kind":"from-file","type":"create","payload":{"transactionList":[{"amount":600,"created":"1982-01-15","externalID":"expend-fdff26494cc77a68"},{"amount":1200,"created":"1982-01-16","externalID":"expend-430c30c77fb0e13b"}]},"response":null,"pending":true}' > ledger.jsonl
+                it "considers them posted" && {
+                  WITH_SNAPSHOT="$snapshot/failure-create-from-yml-file-all-posted" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" from-file --context  default "$fixture/transaction-list.json"
+                }
+                it "posts them again with --repost-pending" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-from-yml-file-default-jobtype-with-context" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --repost-pending from-file --context  default "$fixture/transaction-list.json"
+                }
+              )
             )
           )
         )
       )
-      # TODO: -yes mode (which is prompted otherwise) with actual mock server being up
+      (with "a stand-in server for Expensify"
+        with_program python3
+        (sandbox
+          if hash python3 &>/dev/null; then
+            echo '{"responseCode": 200}' > response.json
+            python3 "$root/stand-in-server.py" port response.json &
+            server=$!
+            # shellcheck disable=2064
+            trap "kill $server" EXIT
+            while ! [ -s port ]; do sleep 0.1; done
+            endpoint="http://127.0.0.1:$(cat port)"
+          fi
+          (when "auto-confirming a post to the endpoint given with --endpoint"
+            it "posts and shows the response of the server" && {
+              WITH_SNAPSHOT="$snapshot/success-post-to-stand-in-server" \
+              expect_run ${SUCCESSFULLY} "$exe" post --context-dir . -y "${CREDS[@]}" --endpoint "${endpoint:-}" from-file <(echo 'somevalue: 42')
+            }
+            it "records the post in the ledger" && {
+              expect_run ${SUCCESSFULLY} test -s ledger.jsonl
+            }
+            it "appends the confirmed entry to the pending one added before posting" && {
+              expect_run ${SUCCESSFULLY} test "$(wc -l < ledger.jsonl)" -eq 2
+            }
+          )
+          (when "auto-confirming a post to the endpoint given with EXPEND_ENDPOINT"
+            it "posts and shows the response of the server" && {
+              EXPEND_ENDPOINT="${endpoint:-}" \
+              WITH_SNAPSHOT="$snapshot/success-post-to-stand-in-server" \
+              expect_run ${SUCCESSFULLY} "$exe" post --context-dir . -y "${CREDS[@]}" from-file <(echo 'somevalue: 42')
+            }
+          )
+          (when "the server rejects the credentials"
+            echo '{"responseCode": 407, "responseMessage": "Authentication error"}' > response.json
+            it "fails with a dedicated exit code and suggests clearing the keychain entry" && {
+              WITH_SNAPSHOT="$snapshot/failure-post-to-stand-in-server-authentication" \
+              expect_run 3 "$exe" post --context-dir . -y "${CREDS[@]}" --endpoint "${endpoint:-}" from-file <(echo 'somevalue: 42')
+            }
+            it "marks the post as failed in the ledger" && {
+              expect_run ${SUCCESSFULLY} grep -q '"failed":true' ledger.jsonl
+            }
+          )
+        )
+      )
+      (when "using an invalid endpoint"
+        it "fails gracefully" && {
+          WITH_SNAPSHOT="$snapshot/failure-post-invalid-endpoint" \
+          expect_run ${WITH_FAILURE} "$exe" post -y "${CREDS[@]}" --endpoint 'not a url' from-file <(echo 'somevalue: 42')
+        }
+      )
     )
   )
 
@@ -95,6 +180,46 @@ WITH_FAILURE=1
                 }
               )
             )
+            (when "using a per-diem kind without a rate for the destination"
+              it "fails gracefully and lists the valid kinds" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-unknown-kind" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem thu breakfast
+              }
+            )
+          )
+        )
+        (with "a default context available (and the time set to a known date)"
+          (when "country is the USA"
+            step "(setting the context)"
+            expect_run ${SUCCESSFULLY} "$exe" context --at . set --country USA --email me@example.com --project 'project code'
+            (when "using the 'single-day' period"
+              (when "using the 'departure' kind"
+                it "succeeds and creates a payload in dollars" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-per-diem-single-day-departure-usa" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem fri departure
+                }
+              )
+            )
+          )
+        )
+        (with "a default context available (and the time set to a known date)"
+          (when "country is Germany and destination is a city with its own rates"
+            step "(setting the context)"
+            expect_run ${SUCCESSFULLY} "$exe" context --at . set --country Germany --destination 'USA-New York' --email me@example.com --project 'project code'
+            (when "using the 'single-day' period"
+              (when "using the 'arrival' kind"
+                it "succeeds and creates a properly formatted payload" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-per-diem-single-day-arrival-germany-for-usa-new-york" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem mon arrival
+                }
+              )
+              (when "using the 'arrival' kind with all meals provided"
+                it "succeeds and deducts no more than the per-diem of the day" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-per-diem-single-day-arrival-all-meals-germany-for-usa-new-york" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem mon arrival --meals breakfast,lunch,dinner
+                }
+              )
+            )
           )
         )
         (with "a default context available (and the time set to a known date)"
@@ -102,6 +227,84 @@ WITH_FAILURE=1
             step "(setting the context)"
             expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code'
 
+            (when "using the 'weekdays except' period"
+              it "succeeds and creates one element per range of days" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-except-wednesday" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem 'weekdays except wed' fullday
+              }
+            )
+            (when "using a period with an invalid day"
+              it "fails gracefully and points at the invalid day" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-period-invalid-day" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem 'mon-wed,frx' fullday
+              }
+            )
+            (when "using a whole month"
+              it "succeeds and creates one element per week" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-month-fullday" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" per-diem 2018-10 fullday
+              }
+            )
+            (when "using multiple weeks and a subset of days"
+              it "succeeds and creates one element per range of days" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weeks-mon-thu-fullday" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem 'weeks:2 mon-thu' fullday
+              }
+            )
+            (when "using a date range across weeks"
+              it "succeeds and creates a single element for the whole range" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-date-range-fullday" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" per-diem 2018-09-27..2018-10-02 fullday
+              }
+            )
+            (when "using a date range in the wrong order"
+              it "fails gracefully" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-date-range-invalid-order" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" per-diem 2018-10-02..2018-09-27 fullday
+              }
+            )
+            (when "using the 'weekdays' period and provided breakfasts"
+              it "succeeds and deducts 20% of the full-day rate for every day" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-fullday-with-breakfast" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday --meals breakfast
+              }
+            )
+            (when "using the 'weekdays' period and providing different meals on some days"
+              it "succeeds and deducts the meals of each day" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-fullday-with-meals-on-some-days" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday --meals breakfast --meals-on tue-wed=lunch --meals-on fri=none
+              }
+            )
+            (when "using an invalid meals override"
+              it "fails gracefully" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-invalid-meals-override" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday --meals-on tue
+              }
+            )
+            (when "overriding the destination of some days"
+              it "succeeds and files each day with the rates of its destination" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-destination-overrides" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday --destination-on tue-wed=India-Other --destination-on fri=home
+              }
+            )
+            (when "providing meals while subtracting"
+              it "fails gracefully" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-meals-when-subtracting" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem --subtract thursday fullday --meals lunch
+              }
+            )
+            (when "providing meals on some days while subtracting"
+              it "fails gracefully" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-meals-when-subtracting" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem --subtract thursday fullday --meals-on thursday=lunch
+              }
+            )
+            (when "providing meals on some days of a per-diem for a meal"
+              it "fails gracefully" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-meals-on-some-days-of-meal" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem monday breakfast --meals-on monday=lunch
+              }
+            )
             (when "using the 'weekdays' period"
               (when "using the 'fullday' kind"
                 it "succeeds and creates a properly formatted payload" && {
@@ -109,6 +312,12 @@ WITH_FAILURE=1
                   expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday
                 }
               )
+              (when "using the 'fullday' kind and an ISO week as reference date"
+                it "succeeds and creates the same payload" && {
+                  WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-fullday" \
+                  expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 1972-W35 per-diem weekdays fullday
+                }
+              )
             )
             (when "using the flexible 'range from-to' period"
               (when "using the 'fullday' kind"
@@ -160,6 +369,27 @@ WITH_FAILURE=1
               )
             )
 
+            (when "the week spans the change of rates at new year"
+              it "splits the range into one element per rate set and tells which rate sets were used" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-fullday-across-rate-sets" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 2019-12-31 per-diem weekdays fullday
+              }
+            )
+
+            (with "a rates override in the context directory"
+              step "(copying the rates override)"
+              cp "$fixture/rates-override.yml" ./rates.yml
+              it "succeeds and uses the overridden rate" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-fullday-with-rates-override" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday
+              }
+              it "accepts destinations defined by the override" && {
+                WITH_SNAPSHOT="$snapshot/success-create-per-diem-destination-from-rates-override" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday --destination-on tue=atlantis
+              }
+              rm ./rates.yml
+            )
+
             (when "using the an unknown per-diem period"
               it "fails gracefully" && {
                 WITH_SNAPSHOT="$snapshot/failure-create-per-diem-unknown-period" \
@@ -167,9 +397,9 @@ WITH_FAILURE=1
               }
             )
 
-            (when "using the an unknown per-diem kind"
+            (when "using the an invalid per-diem kind"
               it "fails gracefully" && {
-                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-unknown-kind" \
+                WITH_SNAPSHOT="$snapshot/failure-create-per-diem-invalid-kind" \
                 expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" per-diem weekdays foobar
               }
             )
@@ -178,6 +408,294 @@ WITH_FAILURE=1
       )
     )
   )
+
+  (with "a week starting on Sunday"
+    (with "dry-run mode"
+      (sandbox
+        step "(setting the context)"
+        expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code' --week-start sunday --time-zone +05:30
+
+        (when "using the 'weekdays' period"
+          it "succeeds and files Sunday to Thursday" && {
+            WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-week-starting-sunday" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 2019-06-05 per-diem weekdays fullday
+          }
+        )
+        (when "using a range wrapping around the start of the week"
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-create-per-diem-range-wrapping-week-start" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 2019-06-05 per-diem mon-sun fullday
+          }
+        )
+      )
+    )
+  )
+
+  (with "holidays"
+    (with "dry-run mode"
+      (sandbox
+        (with "a context with the holidays of Bavaria and a custom calendar"
+          step "(setting the context)"
+          expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code' --holidays BY --holidays "$fixture/holidays.ics"
+
+          (when "using the 'weekdays' period in a week with a holiday"
+            it "succeeds and leaves out the holiday, noting it in the comment" && {
+              WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-skipping-holidays" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 2019-06-19 per-diem weekdays fullday
+            }
+          )
+          (when "using a date range with holidays from the custom calendar"
+            it "succeeds and leaves out the holidays" && {
+              WITH_SNAPSHOT="$snapshot/success-create-per-diem-date-range-skipping-holidays" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" per-diem 2019-07-15..2019-07-19 fullday -m "client visit"
+            }
+          )
+          (when "using a whole month"
+            it "succeeds and leaves out the holidays" && {
+              WITH_SNAPSHOT="$snapshot/success-create-per-diem-month-skipping-holidays" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" per-diem 2019-10 fullday
+            }
+          )
+          (when "including holidays"
+            it "succeeds and files all weekdays" && {
+              WITH_SNAPSHOT="$snapshot/success-create-per-diem-weekdays-including-holidays" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 2019-06-19 --include-holidays per-diem weekdays fullday
+            }
+          )
+        )
+        (when "setting a context with an unknown holiday calendar"
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-set-context-unknown-holidays" \
+            expect_run ${WITH_FAILURE} "$exe" context --at . set --email me@example.com --project 'project code' --holidays XX
+          }
+        )
+      )
+    )
+  )
+
+  (with "per-diems on record"
+    (with "dry-run mode"
+      (sandbox
+        WEEKDATE=(--weekdate 2019-06-05)
+        step "(setting the contexts)"
+        expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code'
+        expect_run ${SUCCESSFULLY} "$exe" context --at . set --name other --email me@example.com --project 'project code'
+        step "(recording per-diems every three weeks since March)"
+        for date in 2019-03-04 2019-03-25 2019-04-15 2019-05-06 2019-05-27; do
+          echo '{"date":"'$date'","kind":"per-diem:fullday","context":"default","project":"project code","amount":2400}'
+        done > record.jsonl
+
+        (when "claiming days after the first three months at the same workplace"
+          it "fails and explains the three-month window" && {
+            WITH_SNAPSHOT="$snapshot/failure-create-per-diem-three-month-rule" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday
+          }
+        )
+        (when "ignoring the three-month rule"
+          it "succeeds and warns about it" && {
+            WITH_SNAPSHOT="$snapshot/success-create-per-diem-ignoring-three-month-rule" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem weekdays fullday --ignore-three-month-rule
+          }
+        )
+        (when "claiming another daily per-diem on a day on record"
+          it "succeeds and reports the conflict" && {
+            WITH_SNAPSHOT="$snapshot/success-create-per-diem-conflicting-with-record" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem 2019-05-27 daytrip
+          }
+        )
+        (when "using another context"
+          it "succeeds as it is another workplace" && {
+            WITH_SNAPSHOT="$snapshot/success-create-per-diem-other-context-on-record" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" per-diem --context other weekdays fullday
+          }
+        )
+      )
+    )
+  )
+
+  (with "the 'trip' subcommand"
+    (with "dry-run mode"
+      (sandbox
+        (with "a default context available"
+          step "(setting the context)"
+          expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code'
+
+          (when "the trip spans several days"
+            it "succeeds and files arrival, full days and departure" && {
+              WITH_SNAPSHOT="$snapshot/success-create-trip-several-days" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T07:30 2019-06-06T19:00 --meals breakfast -m "client visit"
+            }
+          )
+          (when "the trip lasts more than 8 hours on a single day"
+            it "succeeds and files a day trip" && {
+              WITH_SNAPSHOT="$snapshot/success-create-trip-single-day" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip '2019-06-03 07:30' '2019-06-03 16:00'
+            }
+          )
+          (when "the trip passes midnight without overnight stay"
+            it "succeeds and files a day trip on the day with most hours" && {
+              WITH_SNAPSHOT="$snapshot/success-create-trip-no-overnight-stay" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T18:00 2019-06-04T03:00 --no-overnight-stay
+            }
+          )
+          (when "the trip visits several destinations"
+            it "succeeds and files each day with the rates of its destination" && {
+              WITH_SNAPSHOT="$snapshot/success-create-trip-several-destinations" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T07:30 2019-06-08T19:00 --destination-on 2019-06-03..2019-06-05=India-Other --destination-on 2019-06-06..2019-06-07=Singapore
+            }
+          )
+          (when "overriding destination and meals by weekday"
+            it "succeeds and applies them to the days of the week of the trip" && {
+              WITH_SNAPSHOT="$snapshot/success-create-trip-weekday-overrides" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T07:30 2019-06-05T19:00 --destination-on wed=India-Other --meals-on tue=lunch
+            }
+          )
+          (when "an override applies to none of the days of the trip"
+            it "fails gracefully" && {
+              WITH_SNAPSHOT="$snapshot/failure-create-trip-override-outside-of-trip" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T07:30 2019-06-05T19:00 --meals-on 2019-06-10=lunch
+            }
+          )
+          (when "a destination override names an unknown destination"
+            it "fails gracefully" && {
+              WITH_SNAPSHOT="$snapshot/failure-create-trip-unknown-destination-override" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T07:30 2019-06-06T19:00 --destination-on 2019-06-04=Atlantis
+            }
+          )
+          (when "the trip lasts no more than 8 hours"
+            it "fails gracefully" && {
+              WITH_SNAPSHOT="$snapshot/failure-create-trip-too-short" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" trip 2019-06-03T08:00 2019-06-03T16:00
+            }
+          )
+        )
+      )
+    )
+  )
+
+  (with "the 'accommodation' subcommand"
+    (with "dry-run mode"
+      (sandbox
+        WEEKDATE=(--weekdate 2019-06-05)
+        (with "a default context available (and the time set to a known date)"
+          step "(setting the context)"
+          expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code'
+
+          (when "staying overnight in Germany"
+            it "succeeds and files the domestic flat rate for each night" && {
+              WITH_SNAPSHOT="$snapshot/success-create-accommodation-mon-wed" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" accommodation mon-wed -m "client visit"
+            }
+          )
+          (when "staying overnight at several destinations"
+            it "succeeds and files the flat rate of each destination" && {
+              WITH_SNAPSHOT="$snapshot/success-create-accommodation-several-destinations" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" accommodation weekdays --destination-on mon-tue=India-Other --destination-on wed=Singapore
+            }
+          )
+        )
+        (with "a context in a country without accommodation rates"
+          step "(setting the context)"
+          expect_run ${SUCCESSFULLY} "$exe" context --at . set --name uk --country UK --email me@example.com --project 'project code'
+
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-create-accommodation-no-rate" \
+            expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" accommodation --context uk mon
+          }
+        )
+      )
+    )
+  )
+
+  (with "the 'home-office' subcommand"
+    (with "dry-run mode"
+      (sandbox
+        WEEKDATE=(--weekdate 2020-06-03)
+        (with "a default context available (and the time set to a known date)"
+          step "(setting the context)"
+          expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code'
+
+          (when "nothing was recorded yet"
+            it "succeeds and files one element per day" && {
+              WITH_SNAPSHOT="$snapshot/success-create-home-office-weekdays" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" home-office weekdays
+            }
+          )
+          (when "there is no home-office rate yet"
+            it "fails gracefully" && {
+              WITH_SNAPSHOT="$snapshot/failure-create-home-office-no-rate" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" --weekdate 2019-06-05 home-office mon
+            }
+          )
+          (with "days of the same year on record"
+            step "(recording days)"
+            cat > record.jsonl <<EOF
+{"date":"2020-01-06","kind":"home-office","project":"project code","amount":59000}
+{"date":"2019-12-30","kind":"home-office","project":"project code","amount":500}
+EOF
+
+            (when "the yearly maximum is reached"
+              it "succeeds and notes that the maximum is reached" && {
+                WITH_SNAPSHOT="$snapshot/success-create-home-office-reaching-maximum" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" home-office mon-tue
+              }
+            )
+            (when "the yearly maximum would be exceeded"
+              it "fails and explains how to proceed" && {
+                WITH_SNAPSHOT="$snapshot/failure-create-home-office-exceeding-maximum" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" home-office weekdays
+              }
+            )
+            (when "the yearly maximum may be exceeded"
+              it "succeeds and warns about it" && {
+                WITH_SNAPSHOT="$snapshot/success-create-home-office-exceeding-maximum" \
+                expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" home-office weekdays --exceed-yearly-maximum
+              }
+            )
+          )
+        )
+      )
+    )
+  )
+
+  (with "the 'mileage' subcommand"
+    (with "dry-run mode"
+      (sandbox
+        WEEKDATE=(--weekdate 1972-09-02)
+        (with "a default context available (and the time set to a known date)"
+          step "(setting the context)"
+          expect_run ${SUCCESSFULLY} "$exe" context --at . set --email me@example.com --project 'project code'
+
+          (when "using the 'weekdays' period"
+            it "succeeds and creates a properly formatted payload" && {
+              WITH_SNAPSHOT="$snapshot/success-create-mileage-weekdays" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" mileage weekdays 20
+            }
+          )
+          (when "country is the USA"
+            step "(setting the context)"
+            expect_run ${SUCCESSFULLY} "$exe" context --at . set --name usa --country USA --email me@example.com --project 'project code'
+            it "succeeds and uses miles and dollars" && {
+              WITH_SNAPSHOT="$snapshot/success-create-mileage-weekdays-usa" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" mileage --context usa weekdays 20
+            }
+          )
+          (when "using the 'single-day' period with a comment"
+            it "succeeds and creates a properly formatted payload" && {
+              WITH_SNAPSHOT="$snapshot/success-create-mileage-single-day-with-comment" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" mileage thu 42 -m "client visit"
+            }
+          )
+          (when "the distance is too far to file"
+            it "fails gracefully" && {
+              WITH_SNAPSHOT="$snapshot/failure-create-mileage-too-far" \
+              expect_run ${WITH_FAILURE} "$exe" post --context-dir . $DRY "${CREDS[@]}" "${WEEKDATE[@]}" mileage weekdays 4000000000
+            }
+          )
+        )
+      )
+    )
+  )
 )
 
 (sandbox
@@ -191,6 +709,16 @@ WITH_FAILURE=1
       }
     )
 
+    (when 'setting a context with an unknown destination'
+      it 'fails and suggests destinations of the same country' && {
+        WITH_SNAPSHOT="$snapshot/failure-set-unknown-destination" \
+        expect_run ${WITH_FAILURE} "$exe" contexts "${context_dir[@]}" set \
+            --project 'the project name sans sub-project' \
+            --email you@example.com \
+            --destination Spain-Bilbao
+      }
+    )
+
     (when 'setting the default context (with a directory override for sandboxing)'
       it 'succeeds' && {
         WITH_SNAPSHOT="$snapshot/success-set-default" \
@@ -224,7 +752,10 @@ WITH_FAILURE=1
               --destination India-Other \
               --travel-tag-name Travel \
               --travel-tag-unbillable \
-              --category-per-diems-name "perdiem category name"
+              --category-per-diems-name "perdiem category name" \
+              --category-mileage-name "mileage category name" \
+              --category-accommodation-name "accommodation category name" \
+              --category-home-office-name "home-office category name"
         }
         it 'writes the expected file' && {
           expect_snapshot "$snapshot/context-dir-multiple-contexts" .
@@ -237,3 +768,99 @@ WITH_FAILURE=1
     )
   )
 )
+
+(with "the 'history' subcommand"
+  (sandbox
+    (when "nothing was posted yet"
+      it "fails gracefully" && {
+        WITH_SNAPSHOT="$snapshot/failure-history-empty" \
+        expect_run ${WITH_FAILURE} "$exe" history --context-dir .
+      }
+    )
+    (with "a ledger"
+      step "(copying the ledger)"
+      cp "$fixture/ledger.jsonl" .
+
+      (when "not filtering"
+        it "succeeds and shows everything posted" && {
+          WITH_SNAPSHOT="$snapshot/success-history-all" \
+          expect_run ${SUCCESSFULLY} "$exe" history --context-dir .
+        }
+      )
+      (when "filtering by date range and amount"
+        it "succeeds and shows the matching expenses only" && {
+          WITH_SNAPSHOT="$snapshot/success-history-date-range-and-amount" \
+          expect_run ${SUCCESSFULLY} "$exe" history --context-dir . --from 2019-06-04 --to 2019-06-10 --min-amount 12
+        }
+      )
+      (when "filtering by context and kind"
+        it "succeeds and shows the matching posts only" && {
+          WITH_SNAPSHOT="$snapshot/success-history-context-and-kind" \
+          expect_run ${SUCCESSFULLY} "$exe" history --context-dir . --context other --kind mileage
+        }
+      )
+      (when "nothing matches"
+        it "fails gracefully" && {
+          WITH_SNAPSHOT="$snapshot/failure-history-no-match" \
+          expect_run ${WITH_FAILURE} "$exe" history --context-dir . --kind trip
+        }
+      )
+      (when "using an invalid amount"
+        it "fails gracefully" && {
+          WITH_SNAPSHOT="$snapshot/failure-history-invalid-amount" \
+          expect_run ${WITH_FAILURE} "$exe" history --context-dir . --max-amount 4.805
+        }
+      )
+      (with "the 'undo' subcommand"
+        CREDS=(--user-id user --user-secret secret)
+        (when "reverting a post which created expenses"
+          it "shows the negated expenses referring to the original post" && {
+            WITH_SNAPSHOT="$snapshot/success-undo-dry-run" \
+            expect_run ${WITH_FAILURE} "$exe" undo --context-dir . -n "${CREDS[@]}" 1
+          }
+        )
+        (when "reverting a post which did not create expenses"
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-undo-no-expenses" \
+            expect_run ${WITH_FAILURE} "$exe" undo --context-dir . -n "${CREDS[@]}" 3
+          }
+        )
+        (when "reverting a post which does not exist"
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-undo-unknown-entry" \
+            expect_run ${WITH_FAILURE} "$exe" undo --context-dir . -n "${CREDS[@]}" 4
+          }
+        )
+        (when "reverting a post which was undone already"
+          step "(adding the undo to the ledger)"
+          echo '{"timestamp":"2019-06-16T10:00:00+00:00","context":"other","kind":"undo","type":"create","payload":{},"response":{},"undoes":2}' >> ledger.jsonl
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-undo-twice" \
+            expect_run ${WITH_FAILURE} "$exe" undo --context-dir . -n "${CREDS[@]}" 2
+          }
+        )
+        (when "reverting a post which did not complete"
+          step "(adding a pending entry to the ledger)"
+          echo '{"timestamp":"2019-06-17T10:00:00+00:00","context":"default","kind":"per-diem","type":"create","payload":{"transactionList":[{"amount":2400,"created":"2019-06-17","currency":"EUR","merchant":"Full Day"}]},"response":null,"pending":true}' >> ledger.jsonl
+          it "fails gracefully" && {
+            WITH_SNAPSHOT="$snapshot/failure-undo-pending" \
+            expect_run ${WITH_FAILURE} "$exe" undo --context-dir . -n "${CREDS[@]}" 5
+          }
+          it "shows the post with unknown outcome in the history" && {
+            WITH_SNAPSHOT="$snapshot/success-history-pending" \
+            expect_run ${SUCCESSFULLY} "$exe" history --context-dir . --from 2019-06-17
+          }
+        )
+        (when "reverting a post whose previous undo failed or did not complete"
+          step "(adding a failed and a pending undo to the ledger)"
+          echo '{"timestamp":"2019-06-18T10:00:00+00:00","context":"default","kind":"undo","type":"create","payload":{},"response":null,"undoes":1,"failed":true}' >> ledger.jsonl
+          echo '{"timestamp":"2019-06-18T11:00:00+00:00","context":"default","kind":"undo","type":"create","payload":{},"response":null,"undoes":1,"pending":true}' >> ledger.jsonl
+          it "shows the negated expenses referring to the original post" && {
+            WITH_SNAPSHOT="$snapshot/success-undo-dry-run" \
+            expect_run ${WITH_FAILURE} "$exe" undo --context-dir . -n "${CREDS[@]}" 1
+          }
+        )
+      )
+    )
+  )
+)
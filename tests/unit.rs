@@ -1,11 +1,1365 @@
+extern crate chrono;
 extern crate expend;
+extern crate failure;
+extern crate reqwest;
+#[macro_use]
+extern crate serde_json;
+
+mod country {
+    use expend::context::{Country, Currency};
+    use expend::{from_date_string, RateTable};
+
+    #[test]
+    fn all_names_parse_and_have_domestic_and_mileage_rates() {
+        let rates = RateTable::default();
+        let date = from_date_string("2019-06-03").unwrap();
+        for name in Country::NAMES {
+            let country: Country = name.parse().unwrap();
+            assert_eq!(country.to_string(), *name);
+            assert!(rates
+                .rates(&country, None, &date)
+                .unwrap()
+                .fullday
+                .is_some());
+            assert!(rates.mileage(&country, &date).is_ok());
+        }
+    }
+
+    #[test]
+    fn from_str_ignores_case() {
+        assert_eq!("usa".parse::<Country>().unwrap(), Country::USA);
+    }
+
+    #[test]
+    fn currency_format() {
+        assert_eq!(Currency::EUR.format(480), "€4.80");
+        assert_eq!(Currency::EUR.format(-480), "€-4.80");
+        assert_eq!(Currency::USD.format(123_456_789), "$1,234,567.89");
+        assert_eq!(Currency::INR.format(123_456_789), "₹12,34,567.89");
+        assert_eq!(Currency::GBP.format(5), "£0.05");
+    }
+}
+
+mod holidays {
+    use expend::from_date_string;
+    use expend::holidays::{Holidays, State};
+    use std::path::Path;
+
+    fn on(holidays: &Holidays, date: &str) -> Option<String> {
+        holidays.on(&from_date_string(date).unwrap())
+    }
+
+    fn fixture(name: &str) -> Holidays {
+        Holidays::from_file(
+            &Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("fixtures")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn state_from_str_accepts_codes_and_names() {
+        assert_eq!("by".parse::<State>().unwrap(), State::Bavaria);
+        assert_eq!("DE-BY".parse::<State>().unwrap(), State::Bavaria);
+        assert_eq!("bavaria".parse::<State>().unwrap(), State::Bavaria);
+        assert!("XX".parse::<State>().is_err());
+    }
+
+    #[test]
+    fn federal_holidays_depending_on_easter() {
+        let holidays = Holidays::from_state(State::Berlin);
+        assert_eq!(on(&holidays, "2019-04-19").unwrap(), "Good Friday");
+        assert_eq!(on(&holidays, "2019-04-22").unwrap(), "Easter Monday");
+        assert_eq!(on(&holidays, "2019-05-30").unwrap(), "Ascension Day");
+        assert_eq!(on(&holidays, "2019-06-10").unwrap(), "Whit Monday");
+        assert_eq!(on(&holidays, "2019-06-11"), None);
+    }
+
+    #[test]
+    fn holidays_of_some_states_only() {
+        let bavaria = Holidays::from_state(State::Bavaria);
+        let berlin = Holidays::from_state(State::Berlin);
+        assert_eq!(on(&bavaria, "2019-06-20").unwrap(), "Corpus Christi");
+        assert_eq!(on(&berlin, "2019-06-20"), None);
+        assert_eq!(
+            on(&berlin, "2019-03-08").unwrap(),
+            "International Women's Day"
+        );
+        assert_eq!(on(&berlin, "2018-03-08"), None);
+        let mecklenburg = Holidays::from_state(State::MecklenburgVorpommern);
+        assert_eq!(
+            on(&mecklenburg, "2023-03-08").unwrap(),
+            "International Women's Day"
+        );
+        assert_eq!(on(&mecklenburg, "2022-03-08"), None);
+        assert_eq!(
+            on(&Holidays::from_state(State::Saxony), "2018-11-21").unwrap(),
+            "Day of Prayer and Repentance"
+        );
+    }
+
+    #[test]
+    fn from_yaml_file() {
+        let holidays = fixture("holidays.yml");
+        assert_eq!(on(&holidays, "2019-12-24").unwrap(), "Christmas Eve");
+        assert_eq!(on(&holidays, "2019-12-25"), None);
+    }
+
+    #[test]
+    fn from_ics_file_with_multi_day_events() {
+        let holidays = fixture("holidays.ics");
+        assert_eq!(
+            on(&holidays, "2019-07-15").unwrap(),
+            "Company Retreat, all hands"
+        );
+        assert!(on(&holidays, "2019-07-16").is_some());
+        assert_eq!(on(&holidays, "2019-07-17"), None);
+        assert_eq!(on(&holidays, "2019-09-02").unwrap(), "Founders Day");
+    }
+
+    #[test]
+    fn merged() {
+        let holidays = fixture("holidays.yml").merged(Holidays::from_state(State::Hamburg));
+        assert!(on(&holidays, "2019-12-24").is_some());
+        assert!(on(&holidays, "2019-12-25").is_some());
+    }
+}
+
+mod week {
+    use expend::context::{Country, UtcOffset};
+    use expend::holidays::Holidays;
+    use expend::{from_date_string, Context, RateTable, UserContext, Weekday};
+
+    pub fn context(week_start: Option<Weekday>, reference_date: &str) -> Context {
+        Context {
+            name: "default".into(),
+            user: UserContext {
+                project: "project".into(),
+                email: "me@example.com".into(),
+                country: Country::Germany,
+                destination: None,
+                tags: Default::default(),
+                categories: Default::default(),
+                time_zone: None,
+                week_start,
+                holidays: Vec::new(),
+            },
+            reference_date: Some(from_date_string(reference_date).unwrap()),
+            comment: None,
+            rates: RateTable::default(),
+            holidays: Holidays::default(),
+            destinations: Default::default(),
+            meals: Default::default(),
+            record: Default::default(),
+            posted: Default::default(),
+        }
+    }
+
+    #[test]
+    fn starts_on_monday_by_default() {
+        assert_eq!(
+            context(None, "2018-09-30")
+                .start_of_reference_week()
+                .unwrap(),
+            from_date_string("2018-09-24").unwrap()
+        );
+    }
+
+    #[test]
+    fn starts_on_the_configured_day() {
+        let ctx = context(Some(Weekday::Sunday), "2018-09-30");
+        let sunday = ctx.start_of_reference_week().unwrap();
+        assert_eq!(sunday, from_date_string("2018-09-30").unwrap());
+        assert_eq!(
+            Weekday::Monday.to_date_from(&sunday).unwrap(),
+            from_date_string("2018-10-01").unwrap()
+        );
+        assert_eq!(
+            context(Some(Weekday::Sunday), "2018-09-29")
+                .start_of_reference_week()
+                .unwrap(),
+            from_date_string("2018-09-23").unwrap()
+        );
+    }
+
+    #[test]
+    fn utc_offset_from_str() {
+        for (input, expected) in &[
+            ("+05:30", "UTC+05:30"),
+            ("UTC+5:30", "UTC+05:30"),
+            ("-0800", "UTC-08:00"),
+            ("-8", "UTC-08:00"),
+            ("utc", "UTC+00:00"),
+        ] {
+            assert_eq!(
+                input.parse::<UtcOffset>().unwrap().to_string(),
+                *expected,
+                "{}",
+                input
+            );
+        }
+        for input in &["5:30", "+25:00", "+05:60", "UTC+x"] {
+            assert!(input.parse::<UtcOffset>().is_err(), "{}", input);
+        }
+    }
+}
+
+mod record {
+    use expend::from_date_string;
+    use expend::record::{append, Entry, Record};
+    use std::{env, fs, path::Path};
+
+    #[test]
+    fn from_file_skips_empty_lines() {
+        let record = Record::from_file(
+            &Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("fixtures")
+                .join("record.jsonl"),
+        )
+        .unwrap();
+        let entries = record.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].date, from_date_string("2020-01-06").unwrap());
+        assert_eq!(entries[1].project, "other project");
+        assert_eq!(entries[1].amount, -500);
+    }
+
+    #[test]
+    fn missing_files_are_empty_and_created_on_append() {
+        let path = env::temp_dir().join(format!("expend-record-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(Record::from_file(&path).unwrap().entries().is_empty());
+
+        let entry = Entry {
+            date: from_date_string("2020-01-06").unwrap(),
+            kind: "home-office".into(),
+            context: "default".into(),
+            project: "project".into(),
+            amount: 500,
+            external_id: None,
+        };
+        append(&path, &[entry.clone()]).unwrap();
+        append(&path, &[entry.clone()]).unwrap();
+        let record = Record::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(record.entries(), &[entry.clone(), entry][..]);
+    }
+}
+
+mod expensify {
+    use chrono::Utc;
+    use expend::expensify::{had_no_effect, retry_after, Client, Error, Settings};
+    use failure;
+    use reqwest::Url;
+    use serde_json::Value;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn errors_are_classified_by_response_code() {
+        let body = json!({"responseCode": 407, "responseMessage": "Authentication error"});
+        assert_eq!(
+            Error::from_response(407, &body),
+            Error::Authentication {
+                code: 407,
+                message: "Authentication error".into()
+            }
+        );
+        let kind = |code| match Error::from_response(code, &json!({})) {
+            Error::Authentication { .. } => "authentication",
+            Error::Validation { .. } => "validation",
+            Error::RateLimit { .. } => "rate-limit",
+            Error::Server { .. } => "server",
+            Error::Unexpected { .. } => "unexpected",
+        };
+        assert_eq!(kind(401), "authentication");
+        assert_eq!(kind(410), "validation");
+        assert_eq!(kind(429), "rate-limit");
+        assert_eq!(kind(503), "server");
+        assert_eq!(kind(304), "unexpected");
+    }
+
+    #[test]
+    fn errors_without_message_show_the_response() {
+        assert_eq!(
+            format!("{}", Error::from_response(500, &json!({"responseCode": 500}))),
+            "Expensify failed to handle the request with response code 500: {\n  \"responseCode\": 500\n}"
+        );
+    }
+
+    fn http(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            status,
+            body.len(),
+            headers,
+            body
+        )
+    }
+
+    /// Answer one request after another with `responses`, returning the url to use and the number of requests answered.
+    fn serve(responses: Vec<String>) -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let answered = count.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if line.to_ascii_lowercase().starts_with("content-length:") {
+                        length = line[15..].trim().parse().unwrap();
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                answered.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, count)
+    }
+
+    fn post(url: Url, request_type: &str) -> Result<Value, failure::Error> {
+        Client::new(Some(url), "user", "secret")
+            .with_settings(Settings {
+                max_retries: 2,
+                backoff: Duration::from_millis(1),
+                ..Default::default()
+            })
+            .post(request_type, json!({}))
+    }
+
+    const OK: &str = r#"{"responseCode": 200}"#;
+
+    #[test]
+    fn endpoint_keeps_the_path_of_the_host() {
+        let endpoint = |host: Option<&str>| {
+            Client::new(host.map(|h| h.parse().unwrap()), "user", "secret")
+                .endpoint()
+                .to_string()
+        };
+        assert_eq!(
+            endpoint(None),
+            "https://integrations.expensify.com/Integration-Server/ExpensifyIntegrations"
+        );
+        assert_eq!(
+            endpoint(Some("http://localhost:8080")),
+            "http://localhost:8080/Integration-Server/ExpensifyIntegrations"
+        );
+        assert_eq!(
+            endpoint(Some("http://localhost:8080/expensify")),
+            "http://localhost:8080/expensify/Integration-Server/ExpensifyIntegrations"
+        );
+        assert_eq!(
+            endpoint(Some("http://localhost:8080/expensify/")),
+            "http://localhost:8080/expensify/Integration-Server/ExpensifyIntegrations"
+        );
+    }
+
+    #[test]
+    fn creating_is_retried_when_rate_limited() {
+        let (url, count) = serve(vec![
+            http("429 Too Many Requests", "Retry-After: 0\r\n", "{}"),
+            http("200 OK", "", OK),
+        ]);
+        assert_eq!(post(url, "create").unwrap(), json!({"responseCode": 200}));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn creating_is_not_retried_if_it_may_have_succeeded() {
+        let (url, count) = serve(vec![http("500 Internal Server Error", "", "{}")]);
+        let err = post(url, "create").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::Server {
+                code: 500,
+                message: "{}".into()
+            })
+        );
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn other_jobs_are_retried_on_server_errors() {
+        let (url, count) = serve(vec![
+            http("502 Bad Gateway", "", "<html>bad gateway</html>"),
+            http("200 OK", "", OK),
+        ]);
+        assert!(post(url, "get").is_ok());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn retries_are_bounded() {
+        let unavailable = http("503 Service Unavailable", "", "{}");
+        let (url, count) = serve(vec![unavailable.clone(), unavailable.clone(), unavailable]);
+        let err = post(url, "create").unwrap_err();
+        assert_eq!(format!("{}", err), "Gave up after 2 retries");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn long_throttling_hints_are_not_waited_for() {
+        let (url, count) = serve(vec![http(
+            "200 OK",
+            "Retry-After: 3600\r\n",
+            r#"{"responseCode": 429, "responseMessage": "Too many requests"}"#,
+        )]);
+        let err = post(url, "create").unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "Expensify is limiting the rate of requests with response code 429: Too many requests"
+        );
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn refused_connections_had_no_effect() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}", port).parse().unwrap();
+        assert!(had_no_effect(&post(url, "create").unwrap_err()));
+    }
+
+    #[test]
+    fn rejected_requests_had_no_effect_unlike_failing_ones() {
+        let (url, _) = serve(vec![http("200 OK", "", r#"{"responseCode": 410}"#)]);
+        assert!(had_no_effect(&post(url, "create").unwrap_err()));
+        let (url, _) = serve(vec![http("500 Internal Server Error", "", "{}")]);
+        assert!(!had_no_effect(&post(url, "create").unwrap_err()));
+        let (url, _) = serve(vec![http("200 OK", "", "not json")]);
+        assert!(!had_no_effect(&post(url, "create").unwrap_err()));
+    }
+
+    #[test]
+    fn retry_after_is_given_in_seconds_or_as_date() {
+        assert_eq!(retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::from_secs(0))
+        );
+        let later = (Utc::now() + ::chrono::Duration::hours(1)).to_rfc2822();
+        let wait = retry_after(&later).unwrap();
+        assert!(wait > Duration::from_secs(3500) && wait <= Duration::from_secs(3600));
+        assert_eq!(retry_after("soon"), None);
+    }
+}
+
+mod ledger {
+    use expend::expensify::{add_external_ids, external_id};
+    use expend::from_date_string;
+    use expend::ledger::{append, external_ids, read, Entry, Filter};
+    use expend::record::Entry as Record;
+    use std::path::Path;
+
+    fn entries() -> Vec<Entry> {
+        read(
+            &Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("fixtures")
+                .join("ledger.jsonl"),
+        )
+        .unwrap()
+    }
+
+    fn matching(filter: &Filter) -> Vec<(usize, usize)> {
+        entries()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| filter.transactions(e).map(|t| (i, t.len())))
+            .collect()
+    }
+
+    #[test]
+    fn read_keeps_the_order_of_posts() {
+        let entries = entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].context, Some("default".to_owned()));
+        assert_eq!(entries[1].kind, "mileage");
+        assert_eq!(entries[2].context, None);
+        assert!(entries[2].transactions().is_empty());
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        assert_eq!(matching(&Filter::default()), vec![(0, 2), (1, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn filter_by_date_range_skips_posts_without_transactions() {
+        let filter = Filter {
+            from: Some(from_date_string("2019-06-05").unwrap()),
+            to: Some(from_date_string("2019-06-10").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(matching(&filter), vec![(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn filter_by_context_kind_and_amount() {
+        let filter = Filter {
+            context: Some("default".into()),
+            kind: Some("per-diem".into()),
+            min_amount: Some(1200),
+            max_amount: Some(1200),
+            ..Default::default()
+        };
+        assert_eq!(matching(&filter), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn undo_negates_amounts_and_refers_to_the_original() {
+        let mut entry = entries().remove(0);
+        entry.records = vec![Record {
+            date: from_date_string("2019-06-03").unwrap(),
+            kind: "per-diem:fullday".into(),
+            context: "default".into(),
+            project: "project".into(),
+            amount: 2400,
+            external_id: Some("expend-1".into()),
+        }];
+        let (payload, records) = entry.undo(1).unwrap();
+        let transactions = payload["transactionList"].as_array().unwrap();
+        assert_eq!(transactions[0]["amount"], -4800);
+        assert_eq!(
+            transactions[0]["comment"],
+            "Undo of #1 posted on 2019-06-07: 2019-06-03 to 2019-06-04: client visit"
+        );
+        assert_eq!(transactions[1]["amount"], -1200);
+        assert_eq!(
+            transactions[1]["comment"],
+            "Undo of #1 posted on 2019-06-07"
+        );
+        assert_eq!(
+            transactions[1]["merchant"],
+            entry.transactions()[1]["merchant"]
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].amount, -2400);
+        assert_eq!(records[0].kind, "per-diem:fullday");
+        assert_eq!(records[0].external_id, None);
+    }
+
+    #[test]
+    fn undo_requires_created_expenses() {
+        assert_eq!(
+            format!("{}", entries()[2].undo(3).unwrap_err()),
+            "Post #3 of type 'reconciliation' cannot be undone as it did not create any expenses."
+        );
+    }
+
+    #[test]
+    fn external_ids_leave_out_undone_posts() {
+        let post = |id: &str, undoes: Option<usize>| Entry {
+            payload: json!({"transactionList": [{"amount": 100, "externalID": id}]}),
+            undoes,
+            ..entries().remove(1)
+        };
+        let entries = vec![post("a", None), post("b", None), post("c", Some(1))];
+        let ids: Vec<_> = external_ids(&entries, true).into_iter().collect();
+        assert_eq!(ids, vec!["b".to_owned()]);
+    }
+
+    #[test]
+    fn external_ids_of_pending_posts_are_optional() {
+        let entries = vec![Entry {
+            payload: json!({"transactionList": [{"amount": 100, "externalID": "a"}]}),
+            pending: true,
+            ..entries().remove(1)
+        }];
+        assert_eq!(external_ids(&entries, true).len(), 1);
+        assert!(external_ids(&entries, false).is_empty());
+    }
+
+    #[test]
+    fn external_ids_leave_out_failed_posts_and_undos() {
+        let post = |id: &str, undoes: Option<usize>, failed| Entry {
+            payload: json!({"transactionList": [{"amount": 100, "externalID": id}]}),
+            undoes,
+            failed,
+            ..entries().remove(1)
+        };
+        let entries = vec![
+            post("a", None, false),
+            post("b", None, true),
+            post("c", Some(1), true),
+        ];
+        let ids: Vec<_> = external_ids(&entries, true).into_iter().collect();
+        assert_eq!(ids, vec!["a".to_owned()]);
+    }
+
+    #[test]
+    fn outcome_takes_the_place_of_the_pending_entry() {
+        let path =
+            ::std::env::temp_dir().join(format!("expend-ledger-{}.jsonl", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let pending = |entry: Entry| Entry {
+            pending: true,
+            ..entry
+        };
+        let confirmed = Entry {
+            response: json!({"responseCode": 200}),
+            ..entries().remove(1)
+        };
+        let failed = Entry {
+            failed: true,
+            ..entries().remove(2)
+        };
+        append(&path, &entries().remove(0)).unwrap();
+        append(&path, &pending(confirmed.clone())).unwrap();
+        append(&path, &pending(failed.clone())).unwrap();
+        append(&path, &confirmed).unwrap();
+        append(&path, &failed).unwrap();
+        let lines = ::std::fs::read_to_string(&path).unwrap().lines().count();
+        let ledger = read(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines, 5);
+        assert_eq!(ledger, vec![entries().remove(0), confirmed, failed]);
+    }
+
+    #[test]
+    fn external_id_is_stable() {
+        assert_eq!(
+            external_id("default", "1982-01-15", "from-file:20 km @ €0.3 / km", 600),
+            "expend-fdff26494cc77a68"
+        );
+        assert_ne!(
+            external_id("default", "1982-01-15", "per-diem:fullday", 2400),
+            external_id("other", "1982-01-15", "per-diem:fullday", 2400)
+        );
+    }
+
+    #[test]
+    fn add_external_ids_keeps_existing_ones() {
+        let mut payload = json!({"transactionList": [
+            {"amount": 600, "created": "1982-01-15", "merchant": "20 km @ €0.3 / km"},
+            {"amount": 600, "created": "1982-01-15", "externalID": "mine"},
+        ]});
+        add_external_ids(&mut payload, "default");
+        assert_eq!(
+            payload["transactionList"][0]["externalID"],
+            "expend-fdff26494cc77a68"
+        );
+        assert_eq!(payload["transactionList"][1]["externalID"], "mine");
+    }
+
+    #[test]
+    fn add_external_ids_tells_apart_transactions_which_are_alike() {
+        let transaction =
+            json!({"amount": 600, "created": "1982-01-15", "merchant": "20 km @ €0.3 / km"});
+        let mut payload = json!({"transactionList": [transaction, transaction, transaction]});
+        add_external_ids(&mut payload, "default");
+        let ids: Vec<_> = payload["transactionList"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["externalID"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(ids[0], "expend-fdff26494cc77a68");
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[2]);
+        assert_ne!(ids[0], ids[2]);
+    }
+}
+
+mod reference_date {
+    use expend::{from_date_string, parse_reference_date};
+
+    fn parse(s: &str) -> String {
+        parse_reference_date(s, from_date_string("2018-09-27").unwrap())
+            .unwrap()
+            .format("%Y-%m-%d")
+            .to_string()
+    }
+
+    #[test]
+    fn named_days_and_weeks() {
+        assert_eq!(parse("today"), "2018-09-27");
+        assert_eq!(parse("Yesterday"), "2018-09-26");
+        assert_eq!(parse("last-week"), "2018-09-20");
+        assert_eq!(parse("next-week"), "2018-10-04");
+    }
+
+    #[test]
+    fn relative_days_and_weeks() {
+        assert_eq!(parse("-2w"), "2018-09-13");
+        assert_eq!(parse("+1w"), "2018-10-04");
+        assert_eq!(parse("-3d"), "2018-09-24");
+    }
+
+    #[test]
+    fn iso_weeks_are_their_monday() {
+        assert_eq!(parse("2018-W39"), "2018-09-24");
+        assert_eq!(parse("2019-W01"), "2018-12-31");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse("2018-01-02"), "2018-01-02");
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        let today = from_date_string("2018-09-27").unwrap();
+        for s in &["-2y", "-w", "2018-W54", "2018-Wx", "tomorrow", "-2wé"] {
+            assert!(parse_reference_date(s, today).is_err(), "{}", s);
+        }
+    }
+}
 
 mod per_diem {
+    mod meals {
+        use expend::context::MealsOverride;
+        use expend::from_date_string;
+        use expend::perdiem::Meals;
+
+        #[test]
+        fn from_str_ignores_case_whitespace_and_duplicates() {
+            assert_eq!(
+                " Breakfast, dinner,breakfast ".parse::<Meals>().unwrap(),
+                Meals {
+                    breakfast: true,
+                    lunch: false,
+                    dinner: true
+                }
+            );
+        }
+
+        #[test]
+        fn from_str_empty_is_no_meals() {
+            assert!("".parse::<Meals>().unwrap().is_empty());
+        }
+
+        #[test]
+        fn from_str_unknown_meal() {
+            assert!("breakfast,brunch".parse::<Meals>().is_err());
+        }
+
+        #[test]
+        fn overrides_apply_to_the_given_days_only() {
+            let mut ctx = ::week::context(None, "2019-06-05");
+            ctx.override_meals("tue-wed=breakfast, lunch".parse().unwrap())
+                .unwrap();
+            ctx.override_meals("wed=none".parse().unwrap()).unwrap();
+            let on = |date| {
+                ctx.meals_on(&from_date_string(date).unwrap(), "dinner".parse().unwrap())
+                    .to_string()
+            };
+            assert_eq!(on("2019-06-03"), "Dinner");
+            assert_eq!(on("2019-06-04"), "Breakfast, Lunch");
+            assert_eq!(on("2019-06-05"), "");
+        }
+
+        #[test]
+        fn overrides_need_days_and_meals() {
+            for input in &["breakfast", "tue=brunch", "someday=none"] {
+                assert!(input.parse::<MealsOverride>().is_err(), "{}", input);
+            }
+        }
+    }
+
+    mod destination {
+        use expend::context::{Country, Destination, DestinationOverride};
+        use expend::holidays::{Holidays, State};
+        use expend::{from_date_string, RateTable};
+        use std::path::Path;
+
+        fn lookup(name: &str) -> Result<Destination, String> {
+            RateTable::default()
+                .destination(&name.parse().unwrap())
+                .map_err(|e| e.to_string())
+        }
+
+        #[test]
+        fn all_destinations_round_trip_through_display_and_lookup() {
+            let all = RateTable::default().destinations();
+            assert!(all.len() > 200);
+            for d in all {
+                assert_eq!(lookup(&d.to_string()), Ok(d));
+            }
+        }
+
+        #[test]
+        fn all_destinations_have_fullday_partial_and_accommodation_rates() {
+            let rates = RateTable::default();
+            let date = from_date_string("2019-06-03").unwrap();
+            for d in rates.destinations() {
+                let r = rates.rates(&Country::Germany, Some(&d), &date).unwrap();
+                assert!(r.fullday.is_some(), "{}", d);
+                assert!(r.partial.is_some(), "{}", d);
+                assert!(r.accommodation.is_some(), "{}", d);
+            }
+        }
+
+        #[test]
+        fn overrides_apply_to_the_given_days_only() {
+            let mut ctx = ::week::context(None, "2019-06-05");
+            ctx.user.destination = Some("Singapore".parse().unwrap());
+            ctx.override_destination("tue-wed=india other".parse().unwrap())
+                .unwrap();
+            ctx.override_destination("2019-06-05=home".parse().unwrap())
+                .unwrap();
+            let on = |date| {
+                ctx.destination_on(&from_date_string(date).unwrap())
+                    .map(ToString::to_string)
+            };
+            assert_eq!(on("2019-06-03"), Some("Singapore".to_owned()));
+            assert_eq!(on("2019-06-04"), Some("India-Other".to_owned()));
+            assert_eq!(on("2019-06-05"), None);
+        }
+
+        #[test]
+        fn overrides_apply_to_holidays_as_well() {
+            let mut ctx = ::week::context(None, "2019-06-05");
+            ctx.holidays = Holidays::from_state(State::Hamburg);
+            ctx.override_destination("2019-06-07..2019-06-11=India-Other".parse().unwrap())
+                .unwrap();
+            assert_eq!(
+                ctx.destination_on(&from_date_string("2019-06-10").unwrap())
+                    .map(ToString::to_string),
+                Some("India-Other".to_owned())
+            );
+        }
+
+        #[test]
+        fn overrides_need_days_and_a_destination() {
+            for input in &["India-Other", "tue=", "someday=home"] {
+                assert!(input.parse::<DestinationOverride>().is_err(), "{}", input);
+            }
+        }
+
+        #[test]
+        fn overrides_need_a_destination_known_to_the_rates_of_the_context() {
+            let mut ctx = ::week::context(None, "2019-06-05");
+            assert!(ctx
+                .override_destination("tue=Atlantis".parse().unwrap())
+                .is_err());
+            ctx.rates = RateTable::default().merged(
+                RateTable::from_file(
+                    &Path::new(file!())
+                        .parent()
+                        .unwrap()
+                        .join("fixtures")
+                        .join("rates-override.yml"),
+                )
+                .unwrap(),
+            );
+            ctx.override_destination("tue=atlantis".parse().unwrap())
+                .unwrap();
+            assert_eq!(
+                ctx.destination_on(&from_date_string("2019-06-04").unwrap())
+                    .map(ToString::to_string),
+                Some("Atlantis".to_owned())
+            );
+        }
+
+        #[test]
+        fn lookup_ignores_case_dashes_and_whitespace() {
+            let expected = lookup("USA-New York").unwrap();
+            assert_eq!(lookup("usa-new york"), Ok(expected.clone()));
+            assert_eq!(lookup("USANewYork"), Ok(expected));
+        }
+
+        #[test]
+        fn lookup_accepts_names_written_by_previous_versions() {
+            assert_eq!(lookup("IndiaOther").unwrap().to_string(), "India-Other");
+        }
+
+        #[test]
+        fn lookup_suggests_destinations_of_the_same_country() {
+            let err = lookup("India-Goa").unwrap_err();
+            assert!(err.contains("'India-Mumbai'"), "{}", err);
+            assert!(!err.contains("'USA-Other'"), "{}", err);
+        }
+    }
+
+    mod conflicts {
+        use expend::expensify::TransactionList;
+        use expend::perdiem::{Kind, Meals, Mode};
+        use expend::record::Record;
+        use std::path::Path;
+
+        fn notes(period: &str, kind: Kind, mode: Mode) -> Vec<String> {
+            let mut ctx = ::week::context(None, "2020-02-20");
+            ctx.record = Record::from_file(
+                &Path::new(file!())
+                    .parent()
+                    .unwrap()
+                    .join("fixtures")
+                    .join("record-per-diems.jsonl"),
+            )
+            .unwrap();
+            TransactionList::from_per_diem(
+                ctx,
+                period.parse().unwrap(),
+                kind,
+                mode,
+                Meals::default(),
+                false,
+            )
+            .unwrap()
+            .notes
+        }
+
+        #[test]
+        fn different_daily_kinds_on_the_same_day_conflict_with_the_record() {
+            assert!(notes("2020-02-20", Kind::Daytrip, Mode::Add).contains(
+                &"Conflicting per-diems on 2020-02-20: daytrip, fullday, including posts in the local record."
+                    .to_owned()
+            ));
+        }
+
+        #[test]
+        fn the_same_kind_twice_conflicts() {
+            assert!(notes("2020-02-19..2020-02-20", Kind::FullDay, Mode::Add).contains(
+                &"Conflicting per-diems on 2020-02-20: fullday (2 times), including posts in the local record."
+                    .to_owned()
+            ));
+        }
+
+        #[test]
+        fn meal_reductions_may_not_exceed_the_per_diems_of_the_day() {
+            let no_conflicts = notes("2020-02-20", Kind::Lunch, Mode::Subtract);
+            assert!(!no_conflicts.iter().any(|n| n.contains("exceed")));
+            assert!(notes("2020-02-19", Kind::Lunch, Mode::Subtract).contains(
+                &"The meal reductions of €11.20 on 2020-02-19 exceed the per-diems of €0.00 known for that day."
+                    .to_owned()
+            ));
+        }
+    }
+
+    mod three_month_rule {
+        use expend::expensify::TransactionList;
+        use expend::perdiem::{Kind, Meals, Mode};
+        use expend::record::Record;
+        use std::path::Path;
+
+        fn per_diem(date: &str, ignore_three_month_rule: bool) -> Result<TransactionList, String> {
+            let mut ctx = ::week::context(None, date);
+            ctx.record = Record::from_file(
+                &Path::new(file!())
+                    .parent()
+                    .unwrap()
+                    .join("fixtures")
+                    .join("record-per-diems.jsonl"),
+            )
+            .unwrap();
+            TransactionList::from_per_diem(
+                ctx,
+                date.parse().unwrap(),
+                Kind::FullDay,
+                Mode::Add,
+                Meals::default(),
+                ignore_three_month_rule,
+            )
+            .map_err(|e| e.to_string())
+        }
+
+        #[test]
+        fn window_ends_on_the_last_day_of_shorter_months() {
+            assert!(per_diem("2020-02-29", false).is_ok());
+            let err = per_diem("2020-03-02", false).err().unwrap();
+            assert!(
+                err.contains("2020-03-02 is outside of the window from 2019-11-30 to 2020-02-29"),
+                "{}",
+                err
+            );
+        }
+
+        #[test]
+        fn ignoring_the_rule_turns_the_error_into_a_note() {
+            let list = per_diem("2020-03-02", true).unwrap();
+            assert_eq!(list.transaction_list.len(), 1);
+            assert!(list.notes.iter().any(|n| n.contains("three months")));
+        }
+
+        #[test]
+        fn window_starts_anew_after_four_weeks() {
+            assert!(per_diem("2020-03-20", false).is_ok());
+        }
+    }
+
+    mod posted {
+        use expend::expensify::TransactionList;
+        use expend::perdiem::{Kind, Meals, Mode};
+        use expend::Context;
+
+        fn per_diem(ctx: Context, period: &str) -> Result<TransactionList, String> {
+            TransactionList::from_per_diem(
+                ctx,
+                period.parse().unwrap(),
+                Kind::FullDay,
+                Mode::Add,
+                Meals::default(),
+                false,
+            )
+            .map_err(|e| e.to_string())
+        }
+
+        #[test]
+        fn days_posted_before_are_left_out_regardless_of_the_period() {
+            let posted = per_diem(::week::context(None, "2019-06-05"), "mon-wed").unwrap();
+            let ctx = || {
+                let mut ctx = ::week::context(None, "2019-06-05");
+                ctx.posted = posted
+                    .records
+                    .iter()
+                    .filter_map(|r| r.external_id.clone())
+                    .collect();
+                ctx
+            };
+            assert_eq!(ctx().posted.len(), 3);
+
+            let list = per_diem(ctx(), "mon-fri").unwrap();
+            assert_eq!(list.transaction_list.len(), 1);
+            assert_eq!(list.transaction_list[0].created, "2019-06-06");
+            assert_eq!(list.records.len(), 2);
+            assert!(list.notes.contains(
+                &"Skipped 'Full Day' on 2019-06-03, 2019-06-04, 2019-06-05 as it was posted before."
+                    .to_owned()
+            ));
+            assert_eq!(
+                per_diem(ctx(), "tue").err().unwrap(),
+                "All expenses were posted before according to the ledger - there is nothing left to post."
+            );
+        }
+    }
+
+    mod trip {
+        use expend::expensify::TransactionList;
+        use expend::trip::Trip;
+        use expend::{from_date_time_string, Context};
+
+        #[test]
+        fn from_date_time_string_accepts_t_or_space_as_separator() {
+            assert_eq!(
+                from_date_time_string("2018-09-24T07:30").unwrap(),
+                from_date_time_string("2018-09-24 07:30").unwrap()
+            );
+            assert!(from_date_time_string("2018-09-24").is_err());
+        }
+
+        #[test]
+        fn must_end_after_it_started() {
+            let start = from_date_time_string("2018-09-24T07:30").unwrap();
+            assert!(Trip::new(start, start, true).is_err());
+        }
+
+        /// The dates of all meal deductions of a trip from 2019-06-03 to 2019-06-05 with breakfast provided.
+        fn breakfast_deductions(ctx: Context) -> Vec<String> {
+            let trip = Trip::new(
+                from_date_time_string("2019-06-03T07:30").unwrap(),
+                from_date_time_string("2019-06-05T19:00").unwrap(),
+                true,
+            )
+            .unwrap();
+            TransactionList::from_trip(ctx, trip, "breakfast".parse().unwrap(), false)
+                .unwrap()
+                .records
+                .iter()
+                .filter(|r| r.kind == "per-diem:meals")
+                .map(|r| r.date.format("%Y-%m-%d").to_string())
+                .collect()
+        }
+
+        #[test]
+        fn breakfast_is_not_deducted_on_the_first_day() {
+            assert_eq!(
+                breakfast_deductions(::week::context(None, "2019-06-03")),
+                vec!["2019-06-04", "2019-06-05"]
+            );
+            let mut ctx = ::week::context(None, "2019-06-03");
+            ctx.override_meals("2019-06-03=breakfast".parse().unwrap())
+                .unwrap();
+            assert_eq!(
+                breakfast_deductions(ctx),
+                vec!["2019-06-03", "2019-06-04", "2019-06-05"]
+            );
+        }
+    }
+
+    mod rates {
+        use expend::context::Country;
+        use expend::expensify::TransactionList;
+        use expend::perdiem::{Kind, Meals, Mode};
+        use expend::{from_date_string, RateTable};
+        use std::path::Path;
+
+        fn fixture_path(name: &str) -> std::path::PathBuf {
+            Path::new(file!())
+                .parent()
+                .unwrap()
+                .join("fixtures")
+                .join(name)
+        }
+
+        fn fixture(name: &str) -> RateTable {
+            RateTable::from_file(&fixture_path(name)).unwrap()
+        }
+
+        #[test]
+        fn notes_name_the_rate_set_used_for_each_destination() {
+            let mut ctx = ::week::context(None, "2020-02-19");
+            ctx.override_destination("wed=India-Other".parse().unwrap())
+                .unwrap();
+            let list = TransactionList::from_per_diem(
+                ctx,
+                "tue-wed".parse().unwrap(),
+                Kind::FullDay,
+                Mode::Add,
+                Meals::default(),
+                false,
+            )
+            .unwrap();
+            assert_eq!(
+                list.notes,
+                vec![
+                    "Used per-diem rates of rate set '2020' (valid from 2020-01-01) for Germany.",
+                    "Used per-diem rates of rate set '2019' for India-Other."
+                ]
+            );
+        }
+
+        fn fullday_in_germany(rates: &RateTable, date: &str) -> u32 {
+            rates
+                .amount(
+                    &Country::Germany,
+                    None,
+                    &Kind::FullDay,
+                    &from_date_string(date).unwrap(),
+                )
+                .unwrap()
+        }
+
+        #[test]
+        fn home_office_rates_start_in_2020() {
+            let rates = RateTable::default();
+            let home_office =
+                |date| rates.home_office(&Country::Germany, &from_date_string(date).unwrap());
+            assert!(home_office("2019-12-31").is_err());
+            assert_eq!(home_office("2020-01-01").unwrap().rate, 500);
+            assert_eq!(home_office("2023-01-01").unwrap().yearly_maximum, 126000);
+        }
+
+        #[test]
+        fn accommodation_uses_the_rate_of_the_destination() {
+            let rates = RateTable::default();
+            let date = from_date_string("2019-06-03").unwrap();
+            assert_eq!(
+                rates.accommodation(&Country::Germany, None, &date).unwrap(),
+                2000
+            );
+            assert_eq!(
+                rates
+                    .accommodation(
+                        &Country::Germany,
+                        Some(&"India-Other".parse().unwrap()),
+                        &date
+                    )
+                    .unwrap(),
+                8500
+            );
+            assert!(rates.accommodation(&Country::UK, None, &date).is_err());
+        }
+
+        #[test]
+        fn default_table_has_domestic_rates() {
+            assert_eq!(
+                fullday_in_germany(&RateTable::default(), "2019-06-03"),
+                2400
+            );
+        }
+
+        #[test]
+        fn the_latest_valid_rate_set_is_used() {
+            let rates = RateTable::default();
+            assert_eq!(fullday_in_germany(&rates, "2019-12-31"), 2400);
+            assert_eq!(fullday_in_germany(&rates, "2020-01-01"), 2800);
+        }
+
+        #[test]
+        fn the_oldest_rate_set_is_used_for_dates_before_any_other() {
+            assert_eq!(
+                fullday_in_germany(&RateTable::default(), "1972-09-01"),
+                2400
+            );
+        }
+
+        #[test]
+        fn destinations_missing_in_newer_rate_sets_use_older_ones() {
+            let date = from_date_string("2020-06-01").unwrap();
+            let india = "India-Other".parse().unwrap();
+            let rates = RateTable::default();
+            assert_eq!(
+                rates
+                    .amount(&Country::Germany, Some(&india), &Kind::FullDay, &date)
+                    .unwrap(),
+                3200
+            );
+            assert_eq!(
+                rates
+                    .rate_set(&Country::Germany, Some(&india), &date)
+                    .unwrap()
+                    .name,
+                "2019"
+            );
+        }
+
+        #[test]
+        fn missing_kind_for_destination_is_an_error_listing_valid_kinds() {
+            let err = RateTable::default()
+                .amount(
+                    &Country::Germany,
+                    Some(&"India-Other".parse().unwrap()),
+                    &Kind::Breakfast,
+                    &from_date_string("2019-06-03").unwrap(),
+                )
+                .unwrap_err()
+                .to_string();
+            assert!(
+                err.contains("Valid kinds are fullday, arrival, departure, daytrip."),
+                "{}",
+                err
+            );
+        }
+
+        #[test]
+        fn override_replaces_rates_of_mentioned_entries_only() {
+            let rates = RateTable::default().merged(fixture("rates-override.yml"));
+            let date = from_date_string("2019-06-03").unwrap();
+            assert_eq!(fullday_in_germany(&rates, "2019-06-03"), 2800);
+            assert_eq!(fullday_in_germany(&rates, "2020-06-01"), 2800);
+            assert!(rates
+                .amount(&Country::Germany, None, &Kind::Breakfast, &date)
+                .is_err());
+            assert_eq!(
+                rates
+                    .amount(
+                        &Country::Germany,
+                        Some(&"India-Other".parse().unwrap()),
+                        &Kind::FullDay,
+                        &date
+                    )
+                    .unwrap(),
+                3200
+            );
+        }
+
+        #[test]
+        fn rate_sets_are_not_used_after_they_expire() {
+            let rates = RateTable::default().merged(fixture("rates-expiring.yml"));
+            assert_eq!(fullday_in_germany(&rates, "2021-01-31"), 3000);
+            assert_eq!(fullday_in_germany(&rates, "2021-02-01"), 2800);
+        }
+
+        #[test]
+        fn unsupported_version_is_an_error() {
+            assert!(RateTable::from_file(&fixture_path("rates-unsupported-version.yml")).is_err());
+        }
+    }
+
     mod timeperiod {
-        use expend::Weekday::*;
-        use expend::{TimePeriod, TimePeriod::*};
+        use expend::expensify::TransactionList;
+        use expend::perdiem::{Kind, Meals, Mode};
+        use expend::DaySet::{Range, Weekdays};
+        use expend::Weekday::{self, *};
+        use expend::{from_date_string, DaySelection, TimePeriod, TimePeriod::*};
         use std::str::FromStr;
 
+        /// The number of expenses and the days of a full-day per-diem for `period`, in the week of 2019-06-05.
+        fn days(period: &str, week_start: Option<Weekday>) -> Result<(usize, Vec<String>), String> {
+            let list = TransactionList::from_per_diem(
+                ::week::context(week_start, "2019-06-05"),
+                period
+                    .parse()
+                    .map_err(|e: ::failure::Error| e.to_string())?,
+                Kind::FullDay,
+                Mode::Add,
+                Meals::default(),
+                false,
+            )
+            .map_err(|e| e.to_string())?;
+            Ok((
+                list.transaction_list.len(),
+                list.records
+                    .iter()
+                    .map(|r| r.date.format("%Y-%m-%d").to_string())
+                    .collect(),
+            ))
+        }
+
+        fn selection(
+            include: Vec<::expend::DaySet>,
+            exclude: Vec<::expend::DaySet>,
+        ) -> Option<TimePeriod> {
+            Some(Selection(DaySelection { include, exclude }))
+        }
+
+        #[test]
+        fn date_from_str() {
+            assert_eq!(
+                " 2018-09-27 ".parse().ok(),
+                Some(Date(from_date_string("2018-09-27").unwrap()))
+            );
+        }
+
+        #[test]
+        fn daterange_from_str_across_weeks() {
+            assert_eq!(
+                "2018-09-27 .. 2018-10-02".parse().ok(),
+                Some(DateRange {
+                    from: from_date_string("2018-09-27").unwrap(),
+                    to: from_date_string("2018-10-02").unwrap()
+                })
+            );
+        }
+
+        #[test]
+        fn daterange_from_str_same_date() {
+            assert_eq!(
+                "2018-09-27..2018-09-27".parse().ok(),
+                Some(Date(from_date_string("2018-09-27").unwrap()))
+            );
+        }
+
+        #[test]
+        fn daterange_from_str_invalid_order() {
+            assert!(TimePeriod::from_str("2018-10-02..2018-09-27").is_err());
+        }
+
+        #[test]
+        fn daterange_from_str_invalid_date() {
+            assert!(TimePeriod::from_str("2018-09-31").is_err());
+            assert!(TimePeriod::from_str("2018-09-27..2018-10-02..2018-10-03").is_err());
+        }
+
         #[test]
         fn singleday_from_str_uppercase() {
             assert_eq!("Mon".parse().ok(), Some(SingleDay(Monday)));
@@ -48,13 +1402,15 @@ mod per_diem {
         }
 
         #[test]
-        fn dayrange_from_str_from_consequitive_days() {
+        fn anydays_from_str_consecutive_days_are_filed_as_range() {
+            assert_eq!("mon,tue".parse().ok(), Some(Days(vec![Monday, Tuesday])));
             assert_eq!(
-                "mon,tue".parse().ok(),
-                Some(DayRange {
-                    from: Monday,
-                    to: Tuesday
-                })
+                days("mon,tue", None),
+                Ok((1, vec!["2019-06-03".into(), "2019-06-04".into()]))
+            );
+            assert_eq!(
+                days("mon,wed", None),
+                Ok((2, vec!["2019-06-03".into(), "2019-06-05".into()]))
             );
         }
 
@@ -97,8 +1453,42 @@ mod per_diem {
         }
 
         #[test]
-        fn dayrange_from_str_invalid_order() {
-            assert!(TimePeriod::from_str("wednesday-tue").is_err());
+        fn dayrange_wrapping_around_the_start_of_the_week() {
+            assert_eq!(
+                "wednesday-tue".parse().ok(),
+                Some(DayRange {
+                    from: Wednesday,
+                    to: Tuesday
+                })
+            );
+            assert_eq!(
+                days("wednesday-tue", None),
+                Err("The range from Wednesday to Tuesday wraps around the start of the week on Monday".into())
+            );
+            assert_eq!(
+                days("mon-sun", Some(Sunday)),
+                Err(
+                    "The range from Monday to Sunday wraps around the start of the week on Sunday"
+                        .into()
+                )
+            );
+        }
+
+        #[test]
+        fn dayrange_with_sunday_as_start_of_the_week() {
+            assert_eq!(
+                days("sun-thu", Some(Sunday)),
+                Ok((
+                    1,
+                    vec![
+                        "2019-06-02".into(),
+                        "2019-06-03".into(),
+                        "2019-06-04".into(),
+                        "2019-06-05".into(),
+                        "2019-06-06".into()
+                    ]
+                ))
+            );
         }
 
         #[test]
@@ -139,5 +1529,132 @@ mod per_diem {
         fn anydays_from_str_skip_empty() {
             assert_eq!("mon, , ,sun".parse().ok(), Some(Days(vec![Monday, Sunday])));
         }
+
+        #[test]
+        fn ranges_from_str_mixed_ranges_and_days() {
+            assert_eq!(
+                "mon-wed,fri".parse().ok(),
+                selection(
+                    vec![Range(Monday, Wednesday), Range(Friday, Friday)],
+                    vec![]
+                )
+            );
+        }
+
+        #[test]
+        fn ranges_join_adjacent_ranges() {
+            assert_eq!(
+                days("mon-tue, wed-thu", None).map(|(expenses, days)| (expenses, days.len())),
+                Ok((1, 4))
+            );
+            assert_eq!(
+                days("mon-wed,thu,fri", None).map(|(expenses, days)| (expenses, days.len())),
+                Ok((1, 5))
+            );
+        }
+
+        #[test]
+        fn except_from_str() {
+            assert_eq!(
+                "weekdays except wed".parse().ok(),
+                selection(vec![Weekdays], vec![Range(Wednesday, Wednesday)])
+            );
+            assert_eq!(
+                "Weekdays EXCEPT mon, fri".parse().ok(),
+                selection(
+                    vec![Weekdays],
+                    vec![Range(Monday, Monday), Range(Friday, Friday)]
+                )
+            );
+            assert_eq!(
+                days("Weekdays EXCEPT mon, fri", None),
+                Ok((
+                    1,
+                    vec![
+                        "2019-06-04".into(),
+                        "2019-06-05".into(),
+                        "2019-06-06".into()
+                    ]
+                ))
+            );
+            assert_eq!(
+                days("mon-sun except tue-sat", None),
+                Ok((2, vec!["2019-06-03".into(), "2019-06-09".into()]))
+            );
+        }
+
+        #[test]
+        fn except_with_sunday_as_start_of_the_week() {
+            assert_eq!(
+                days("weekdays except wed", Some(Sunday)),
+                Ok((
+                    2,
+                    vec![
+                        "2019-06-02".into(),
+                        "2019-06-03".into(),
+                        "2019-06-04".into(),
+                        "2019-06-06".into()
+                    ]
+                ))
+            );
+        }
+
+        #[test]
+        fn except_from_str_invalid() {
+            assert!(TimePeriod::from_str("weekdays except").is_err());
+            assert!(TimePeriod::from_str("except mon").is_err());
+            assert!(TimePeriod::from_str("weekdays except mon except tue").is_err());
+            assert_eq!(
+                days("mon except mon", None),
+                Err("There are no days left in 'Monday except Monday'".into())
+            );
+        }
+
+        #[test]
+        fn month_from_str() {
+            assert_eq!(
+                "month".parse().ok(),
+                Some(Month {
+                    month: None,
+                    days: None
+                })
+            );
+            assert_eq!(
+                "2018-10 mon,wed".parse().ok(),
+                Some(Month {
+                    month: Some((2018, 10)),
+                    days: Some(DaySelection {
+                        include: vec![Range(Monday, Monday), Range(Wednesday, Wednesday)],
+                        exclude: vec![]
+                    })
+                })
+            );
+            assert!(TimePeriod::from_str("2018-13").is_err());
+            assert!(TimePeriod::from_str("month 2018-10-01").is_err());
+        }
+
+        #[test]
+        fn weeks_from_str() {
+            assert_eq!(
+                "weeks:3 weekdays except fri".parse().ok(),
+                Some(Weeks {
+                    weeks: 3,
+                    days: Some(DaySelection {
+                        include: vec![Weekdays],
+                        exclude: vec![Range(Friday, Friday)]
+                    })
+                })
+            );
+            assert!(TimePeriod::from_str("weeks:0").is_err());
+            assert!(TimePeriod::from_str("weeks:x").is_err());
+        }
+
+        #[test]
+        fn from_str_error_points_at_offending_token() {
+            let err = TimePeriod::from_str("mon-wed, frx")
+                .unwrap_err()
+                .to_string();
+            assert!(err.ends_with("mon-wed, frx\n         ^^^"), "{}", err);
+        }
     }
 }